@@ -0,0 +1,71 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use bita::archive::BUZHASH_SEED;
+use bita::buzhash::BuzHash;
+use bita::chunker::{Chunker, ChunkerParams};
+
+const DATA_SIZE: usize = 16 * 1024 * 1024;
+
+// Pseudo random test data
+fn test_data() -> Vec<u8> {
+    let mut seed: u64 = 0x1f23_ab13;
+    (0..DATA_SIZE)
+        .map(|_| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            (seed >> 56) as u8
+        })
+        .collect()
+}
+
+fn scan_all(params: &ChunkerParams, data: &[u8]) -> usize {
+    let mut src = data;
+    let mut chunker = Chunker::new(params.clone(), &mut src);
+    let mut chunks = 0;
+    while let Some((_offset, _data)) = chunker.scan().expect("scan") {
+        chunks += 1;
+    }
+    chunks
+}
+
+fn chunker_scan(c: &mut Criterion) {
+    let data = test_data();
+    let mut group = c.benchmark_group("chunker");
+    group.throughput(Throughput::Bytes(DATA_SIZE as u64));
+    group.sample_size(10);
+    for (name, params) in &[
+        // Default compress parameters (64KiB average chunk size)
+        (
+            "buzhash 64KiB",
+            ChunkerParams::new(15, 16 * 1024, 16 * 1024 * 1024, 16, BUZHASH_SEED),
+        ),
+        (
+            "buzhash 4KiB",
+            ChunkerParams::new(11, 1024, 64 * 1024, 16, BUZHASH_SEED),
+        ),
+        (
+            "fastcdc 64KiB",
+            ChunkerParams::new_fastcdc(15, 16 * 1024, 16 * 1024 * 1024),
+        ),
+    ] {
+        group.bench_function(*name, |b| b.iter(|| scan_all(params, &data)));
+    }
+    group.finish();
+}
+
+fn buzhash_input(c: &mut Criterion) {
+    let data = test_data();
+    let mut group = c.benchmark_group("buzhash");
+    group.throughput(Throughput::Bytes(DATA_SIZE as u64));
+    group.sample_size(10);
+    group.bench_function("input_slice", |b| {
+        let mut hash = BuzHash::new(16, BUZHASH_SEED);
+        b.iter(|| {
+            hash.input_slice(&data);
+            hash.sum()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, chunker_scan, buzhash_input);
+criterion_main!(benches);
@@ -1,13 +1,16 @@
-use protobuf::Message;
+use protobuf::{Message, SingularPtrField};
 
 use blake2::{Blake2b, Digest};
+use ed25519_dalek::Keypair;
 use std::fmt;
 use std::mem;
 
 use crate::chunk_dictionary;
+use crate::chunk_dictionary::ChunkDictionary_SignatureType;
 use crate::chunker_utils::HashBuf;
 use crate::compression::Compression;
 use crate::errors::*;
+use crate::signing;
 use crate::string_utils::*;
 
 pub const BUZHASH_SEED: u32 = 0x1032_4195;
@@ -24,6 +27,9 @@ pub struct ChunkDescriptor {
     pub archive_size: u32,
     pub archive_offset: u64,
     pub source_size: u32,
+
+    // Compression of the chunk if other than the archive chunk compression
+    pub compression: Option<Compression>,
 }
 
 impl From<ChunkDescriptor> for chunk_dictionary::ChunkDescriptor {
@@ -33,6 +39,7 @@ impl From<ChunkDescriptor> for chunk_dictionary::ChunkDescriptor {
             archive_size: dict.archive_size,
             archive_offset: dict.archive_offset,
             source_size: dict.source_size,
+            compression: SingularPtrField::from_option(dict.compression.map(|c| c.into())),
             unknown_fields: std::default::Default::default(),
             cached_size: std::default::Default::default(),
         }
@@ -46,6 +53,7 @@ impl From<chunk_dictionary::ChunkDescriptor> for ChunkDescriptor {
             archive_size: dict.archive_size,
             archive_offset: dict.archive_offset,
             source_size: dict.source_size,
+            compression: dict.compression.into_option().map(Compression::from),
         }
     }
 }
@@ -65,15 +73,26 @@ impl fmt::Display for chunk_dictionary::ChunkDictionary {
 }
 
 pub fn u64_from_le_slice(v: &[u8]) -> u64 {
-    let mut tmp: [u8; 8] = Default::default();;
+    let mut tmp: [u8; 8] = Default::default();
     tmp.copy_from_slice(v);
     u64::from_le_bytes(tmp)
 }
 
+// Build the archive header. If the dictionary signature type is set the header
+// is signed using the given key.
 pub fn build_header(
     dictionary: &chunk_dictionary::ChunkDictionary,
     chunk_data_offset: Option<u64>,
+    signing_key: Option<&Keypair>,
 ) -> Result<Vec<u8>> {
+    let signature_size = match (dictionary.signature_type, signing_key) {
+        (ChunkDictionary_SignatureType::NONE, None) => 0,
+        (ChunkDictionary_SignatureType::ED25519, Some(_)) => signing::SIGNATURE_SIZE,
+        (ChunkDictionary_SignatureType::NONE, Some(_)) => {
+            bail!("signing key given for unsigned header")
+        }
+        (ChunkDictionary_SignatureType::ED25519, None) => bail!("no key to sign header with"),
+    };
     let mut header: Vec<u8> = vec![];
     let mut hasher = Blake2b::new();
     let mut dictionary_buf: Vec<u8> = Vec::new();
@@ -94,13 +113,19 @@ pub fn build_header(
     // Start of archive chunk data, absolute to the archive start
     let offset = match chunk_data_offset {
         Some(o) => o,
-        None => header.len() as u64 + 8 + 64,
+        None => (header.len() + 8 + 64 + signature_size) as u64,
     };
     header.extend(&(offset as u64).to_le_bytes());
 
     // Create and store hash of full header
     hasher.input(&header);
-    header.extend(&hasher.result());
+    let header_checksum = hasher.result();
+    header.extend(&header_checksum);
+
+    // Signature of the header hash
+    if let Some(key) = signing_key {
+        header.extend(signing::sign_header(key, &header_checksum));
+    }
 
     Ok(header)
 }
@@ -1,19 +1,30 @@
 use blake2::{Blake2b, Digest};
+use ed25519_dalek::PublicKey;
 use threadpool::ThreadPool;
 
+use std::cell::RefCell;
+use std::cmp;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::io::prelude::*;
+use std::ops::Range;
+use std::sync::Arc;
 
 use crate::archive;
 use crate::chunk_dictionary;
+use crate::chunk_dictionary::{ChunkDictionary_SignatureType, ChunkerParameters_ChunkingAlgorithm};
 use crate::chunker::ChunkerParams;
 use crate::chunker_utils::HashBuf;
-use crate::compression::Compression;
+use crate::compression::{Compression, Decompressor, ZstdDictionary};
 use crate::errors::*;
 use crate::para_pipe::ParaPipe;
+use crate::signing;
 use crate::string_utils::*;
 
+// Size range (in archive) of the batches of chunks which are grouped and read
+const GROUP_BATCH_MIN_SIZE: u64 = 1024 * 1024;
+const GROUP_BATCH_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
 pub struct ArchiveReader {
     // Go from chunk hash to archive chunk index (chunks vector)
     chunk_map: HashMap<HashBuf, usize>,
@@ -32,10 +43,14 @@ pub struct ArchiveReader {
 
     // Checksum (blake2) of header.
     pub header_checksum: Vec<u8>,
+    pub header_signature: Option<Vec<u8>>,
 
     // Compression used for all chunks
     pub chunk_compression: Compression,
 
+    // Dictionary used for zstd compressed chunks
+    pub zstd_dictionary: Option<Arc<ZstdDictionary>>,
+
     pub created_by_app_version: String,
     pub archive_chunks_offset: u64,
 
@@ -63,7 +78,50 @@ impl fmt::Display for ArchiveReader {
     }
 }
 
+// A chunk in a group of chunks to read from archive, or a gap of unused data
+// between two chunks in a group.
+enum GroupItem<'a> {
+    Chunk(&'a archive::ChunkDescriptor),
+    Gap(u64),
+}
+
+impl<'a> GroupItem<'a> {
+    fn archive_size(&self) -> u64 {
+        match self {
+            GroupItem::Chunk(chunk) => u64::from(chunk.archive_size),
+            GroupItem::Gap(size) => *size,
+        }
+    }
+
+    fn archive_offset(&self) -> Option<u64> {
+        match self {
+            GroupItem::Chunk(chunk) => Some(chunk.archive_offset),
+            GroupItem::Gap(_) => None,
+        }
+    }
+}
+
 // Trait to implement for archive backends.
+// Archive data held in memory by a backend, like a memory mapped archive, which
+// can be shared with the threads decompressing chunks.
+pub type SharedArchiveData = Arc<dyn AsRef<[u8]> + Send + Sync>;
+
+// Compressed chunk data to decompress, either read from the backend or a
+// range of the archive data shared by the backend.
+enum ChunkInput {
+    Read(Vec<u8>),
+    Shared(SharedArchiveData, Range<usize>),
+}
+
+impl ChunkInput {
+    fn data(&self) -> &[u8] {
+        match self {
+            ChunkInput::Read(data) => data,
+            ChunkInput::Shared(data, range) => &(**data).as_ref()[range.clone()],
+        }
+    }
+}
+
 pub trait ArchiveBackend
 where
     Self: Read,
@@ -73,13 +131,73 @@ where
     // given offset.
     fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()>;
 
-    // Read and return chunked data
-    fn read_in_chunks<F: FnMut(Vec<u8>) -> Result<()>>(
+    // Read chunked data. The data given to the callback is only valid during
+    // the call, which lets the backend reuse its buffer for every chunk.
+    fn read_in_chunks(
         &mut self,
         start_offset: u64,
         chunk_sizes: &[u64],
-        chunk_callback: F,
+        chunk_callback: &mut dyn FnMut(&[u8]) -> Result<()>,
     ) -> Result<()>;
+
+    // Max size of a gap between two chunks which is worth reading (and
+    // discarding) to fetch the chunks with a single read.
+    fn max_gap_size(&self) -> u64 {
+        0
+    }
+
+    // Get the archive data if the backend holds all of it in memory. Chunks are
+    // then decompressed straight from the shared data instead of being read
+    // into a buffer each.
+    fn shared_data(&self) -> Option<SharedArchiveData> {
+        None
+    }
+
+    // Read chunked data of several groups of chunks, where each
+    // group is given by its start offset and chunk sizes. The chunks are
+    // returned in the same order as given.
+    fn read_chunk_groups(
+        &mut self,
+        groups: &[(u64, Vec<u64>)],
+        chunk_callback: &mut dyn FnMut(&[u8]) -> Result<()>,
+    ) -> Result<()> {
+        for (start_offset, chunk_sizes) in groups {
+            self.read_in_chunks(*start_offset, chunk_sizes, chunk_callback)?;
+        }
+        Ok(())
+    }
+}
+
+// Allow a backend chosen at runtime to be used as any other backend
+impl<T: ArchiveBackend + ?Sized> ArchiveBackend for Box<T> {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        (**self).read_at(offset, buf)
+    }
+
+    fn read_in_chunks(
+        &mut self,
+        start_offset: u64,
+        chunk_sizes: &[u64],
+        chunk_callback: &mut dyn FnMut(&[u8]) -> Result<()>,
+    ) -> Result<()> {
+        (**self).read_in_chunks(start_offset, chunk_sizes, chunk_callback)
+    }
+
+    fn max_gap_size(&self) -> u64 {
+        (**self).max_gap_size()
+    }
+
+    fn shared_data(&self) -> Option<SharedArchiveData> {
+        (**self).shared_data()
+    }
+
+    fn read_chunk_groups(
+        &mut self,
+        groups: &[(u64, Vec<u64>)],
+        chunk_callback: &mut dyn FnMut(&[u8]) -> Result<()>,
+    ) -> Result<()> {
+        (**self).read_chunk_groups(groups, chunk_callback)
+    }
 }
 
 impl ArchiveReader {
@@ -146,6 +264,19 @@ impl ArchiveReader {
         let offs = archive::PRE_HEADER_SIZE + dictionary_size;
         let chunk_data_offset = archive::u64_from_le_slice(&header_buf[offs..(offs + 8)]) as usize;
 
+        // Read the header signature placed after the header hash
+        let header_signature = match dictionary.signature_type {
+            ChunkDictionary_SignatureType::NONE => None,
+            ChunkDictionary_SignatureType::ED25519 => {
+                let offs = header_buf.len();
+                header_buf.resize(offs + signing::SIGNATURE_SIZE, 0);
+                input
+                    .read_exact(&mut header_buf[offs..])
+                    .chain_err(|| "unable to read archive")?;
+                Some(header_buf[offs..].to_vec())
+            }
+        };
+
         // Create map to go from chunk hash to descriptor index
         let mut chunk_descriptors: Vec<archive::ChunkDescriptor> = Vec::new();
         let mut chunk_map: HashMap<HashBuf, usize> = HashMap::new();
@@ -173,24 +304,43 @@ impl ArchiveReader {
             chunk_descriptors,
             chunk_offsets,
             header_checksum,
+            header_signature,
             header_size: header_buf.len(),
             source_total_size: dictionary.source_total_size,
             source_checksum: dictionary.source_checksum,
             created_by_app_version: dictionary.application_version,
             chunk_compression: dictionary.chunk_compression.unwrap().into(),
+            zstd_dictionary: if dictionary.zstd_dictionary.is_empty() {
+                None
+            } else {
+                Some(Arc::new(ZstdDictionary::new(
+                    dictionary.zstd_dictionary,
+                    &[],
+                )))
+            },
             rebuild_order: dictionary
                 .rebuild_order
                 .into_iter()
                 .map(|s| s as usize)
                 .collect(),
             archive_chunks_offset: chunk_data_offset as u64,
-            chunker_params: ChunkerParams::new(
-                chunker_params.chunk_filter_bits,
-                chunker_params.min_chunk_size as usize,
-                chunker_params.max_chunk_size as usize,
-                chunker_params.hash_window_size as usize,
-                archive::BUZHASH_SEED,
-            ),
+            chunker_params: match chunker_params.chunking_algorithm {
+                ChunkerParameters_ChunkingAlgorithm::BUZHASH => ChunkerParams::new(
+                    chunker_params.chunk_filter_bits,
+                    chunker_params.min_chunk_size as usize,
+                    chunker_params.max_chunk_size as usize,
+                    chunker_params.hash_window_size as usize,
+                    archive::BUZHASH_SEED,
+                ),
+                ChunkerParameters_ChunkingAlgorithm::FIXED_SIZE => {
+                    ChunkerParams::new_fixed_size(chunker_params.max_chunk_size as usize)
+                }
+                ChunkerParameters_ChunkingAlgorithm::FASTCDC => ChunkerParams::new_fastcdc(
+                    chunker_params.chunk_filter_bits,
+                    chunker_params.min_chunk_size as usize,
+                    chunker_params.max_chunk_size as usize,
+                ),
+            },
             hash_length: chunker_params.chunk_hash_length as usize,
         })
     }
@@ -210,11 +360,33 @@ impl ArchiveReader {
             .sum()
     }
 
+    // Verify that the archive header is signed by the given key
+    pub fn verify_signature(&self, key: &PublicKey) -> Result<()> {
+        match self.header_signature {
+            Some(ref signature) => signing::verify_header(key, &self.header_checksum, signature),
+            None => bail!("archive header is not signed"),
+        }
+    }
+
     // Get a set of all chunks present in archive
     pub fn chunk_hash_set(&self) -> HashSet<HashBuf> {
         self.chunk_map.iter().map(|x| x.0.clone()).collect()
     }
 
+    // Get all chunks in source order together with their offset in source
+    pub fn source_layout(&self) -> Vec<(u64, &archive::ChunkDescriptor)> {
+        let mut offset: u64 = 0;
+        self.rebuild_order
+            .iter()
+            .map(|descriptor_index| {
+                let descriptor = &self.chunk_descriptors[*descriptor_index];
+                let chunk_offset = offset;
+                offset += u64::from(descriptor.source_size);
+                (chunk_offset, descriptor)
+            })
+            .collect()
+    }
+
     // Get source offsets of a chunk
     pub fn chunk_source_offsets(&self, hash: &[u8]) -> Vec<u64> {
         if let Some(index) = self.chunk_map.get(hash) {
@@ -224,65 +396,70 @@ impl ArchiveReader {
         }
     }
 
-    // Group chunks which are placed in sequence inside archive
-    fn group_chunks_in_sequence(
-        mut chunks: Vec<&archive::ChunkDescriptor>,
-    ) -> Vec<Vec<&archive::ChunkDescriptor>> {
+    // Group chunks which are placed in sequence inside archive. Chunks separated
+    // by a gap of at most max_gap bytes are also grouped, then the gap is read
+    // and discarded to save a request.
+    fn group_chunks(chunks: Vec<&archive::ChunkDescriptor>, max_gap: u64) -> Vec<Vec<GroupItem>> {
         let mut group_list = vec![];
-        if chunks.is_empty() {
-            return group_list;
-        }
-        let mut group = vec![chunks.remove(0)];
-        while !chunks.is_empty() {
-            let chunk = chunks.remove(0);
-
-            let prev_chunk_end = group.last().unwrap().archive_offset
-                + u64::from(group.last().unwrap().archive_size);
-
-            if prev_chunk_end == chunk.archive_offset {
-                // Chunk is placed right next to the previous chunk
-                group.push(chunk);
-            } else {
-                group_list.push(group);
-                group = vec![chunk];
+        let mut group: Vec<GroupItem> = vec![];
+        let mut prev_chunk_end = 0;
+        for chunk in chunks {
+            if !group.is_empty() {
+                if prev_chunk_end == chunk.archive_offset {
+                    // Chunk is placed right next to the previous chunk
+                } else if chunk.archive_offset > prev_chunk_end
+                    && chunk.archive_offset - prev_chunk_end <= max_gap
+                {
+                    group.push(GroupItem::Gap(chunk.archive_offset - prev_chunk_end));
+                } else {
+                    group_list.push(group);
+                    group = vec![];
+                }
             }
+            prev_chunk_end = chunk.archive_offset + u64::from(chunk.archive_size);
+            group.push(GroupItem::Chunk(chunk));
+        }
+        if !group.is_empty() {
+            group_list.push(group);
         }
-        group_list.push(group);
         group_list
     }
 
+    // Get the compression used for a chunk in archive
+    pub fn chunk_compression_of(&self, descriptor: &archive::ChunkDescriptor) -> Compression {
+        match descriptor.compression {
+            Some(compression) => compression,
+            // Chunks which did not compress are stored uncompressed
+            None if descriptor.archive_size == descriptor.source_size => Compression::None,
+            None => self.chunk_compression,
+        }
+    }
+
+    // Decompress a chunk and verify the chunk data by hash. The chunk data is
+    // then available from the decompressor.
     pub fn decompress_and_verify(
+        decompressor: &mut Decompressor,
         hash_length: usize,
         compression: Compression,
         archive_checksum: &[u8],
+        archive_data: &[u8],
         source_size: usize,
-        archive_data: Vec<u8>,
-    ) -> Result<Vec<u8>> {
-        let mut hasher = Blake2b::new();
-        let chunk_data = if archive_data.len() == source_size {
-            // Archive data is not compressed
-            archive_data
-        } else {
-            let mut decompress_buf = vec![];
-            compression.decompress(archive_data, &mut decompress_buf)?;
-            decompress_buf
-        };
-
-        // Verify data by hash
-        hasher.input(&chunk_data);
-        let checksum = hasher.result().to_vec();
+    ) -> Result<()> {
+        let checksum = decompressor.decompress(compression, archive_data, source_size)?;
         if checksum[..hash_length] != archive_checksum[..hash_length] {
             bail!(
                 "Chunk hash mismatch (expected: {}, got: {})",
-                HexSlice::new(&checksum[0..hash_length]),
-                HexSlice::new(&archive_checksum[0..hash_length])
+                HexSlice::new(&archive_checksum[0..hash_length]),
+                HexSlice::new(&checksum[0..hash_length])
             );
         }
 
-        Ok(chunk_data)
+        Ok(())
     }
 
-    // Get chunk data for all listed chunks if present in archive
+    // Get chunk data for all listed chunks if present in archive. The input may
+    // be a separate backend holding only the chunk data if the header is
+    // detached from the archive.
     pub fn read_chunk_data<T, F>(
         &self,
         pool: &ThreadPool,
@@ -304,52 +481,126 @@ impl ArchiveReader {
 
         let mut total_read = 0;
 
+        // Decompressors and input buffers are given back when their chunk has
+        // been forwarded and then reused, to avoid allocating new chunk buffers
+        // for every chunk.
+        let free_decompressors: RefCell<Vec<Decompressor>> = RefCell::new(Vec::new());
+        let free_buffers: RefCell<Vec<Vec<u8>>> = RefCell::new(Vec::new());
+
         // Setup a parallel pipe for decompression and verify chunk data
         let hash_length = self.hash_length;
-        let chunk_compression = self.chunk_compression;
-        let mut pipe = ParaPipe::new_output(pool, |(chunk_data, checksum): (Vec<u8>, HashBuf)| {
-            // For each offset where this chunk was found in source
-            chunk_callback(checksum, &chunk_data).expect("forward chunk");
-        });
+        let mut pipe = ParaPipe::new_output(
+            pool,
+            |(decompressor, checksum, chunk_input): (Decompressor, HashBuf, ChunkInput)| {
+                // For each offset where this chunk was found in source
+                chunk_callback(checksum, decompressor.data()).expect("forward chunk");
+                free_decompressors.borrow_mut().push(decompressor);
+                if let ChunkInput::Read(buf) = chunk_input {
+                    free_buffers.borrow_mut().push(buf);
+                }
+            },
+        );
+
+        let mut decompress_chunk = |chunk_descriptor: &archive::ChunkDescriptor,
+                                    chunk_input: ChunkInput| {
+            let decompressor = free_decompressors
+                .borrow_mut()
+                .pop()
+                .unwrap_or_else(|| Decompressor::new(self.zstd_dictionary.clone()));
+            pipe.input(
+                (
+                    decompressor,
+                    chunk_descriptor.checksum.clone(),
+                    self.chunk_compression_of(chunk_descriptor),
+                    chunk_descriptor.source_size as usize,
+                    chunk_input,
+                ),
+                move |(mut decompressor, checksum, compression, source_size, chunk_input): (
+                    Decompressor,
+                    HashBuf,
+                    Compression,
+                    usize,
+                    ChunkInput,
+                )| {
+                    Self::decompress_and_verify(
+                        &mut decompressor,
+                        hash_length,
+                        compression,
+                        &checksum,
+                        chunk_input.data(),
+                        source_size,
+                    )
+                    .expect("decompression failed");
+                    (decompressor, checksum, chunk_input)
+                },
+            );
+        };
 
-        // Create groups of chunks so that we can make a single request for all chunks
-        // which are placed in sequence in archive.
-        let grouped_chunks = Self::group_chunks_in_sequence(descriptors);
+        if let Some(shared_data) = input.shared_data() {
+            // Decompress chunks straight from the archive data held by the backend
+            let data_size = (*shared_data).as_ref().len();
+            for chunk_descriptor in descriptors {
+                let start = (self.archive_chunks_offset + chunk_descriptor.archive_offset) as usize;
+                let end = start + chunk_descriptor.archive_size as usize;
+                if end > data_size {
+                    bail!("failed to read archive (read beyond end of archive)");
+                }
+                total_read += chunk_descriptor.archive_size as u64;
+                decompress_chunk(
+                    chunk_descriptor,
+                    ChunkInput::Shared(shared_data.clone(), start..end),
+                );
+            }
+            return Ok(total_read);
+        }
 
-        for group in grouped_chunks {
-            // For each group of chunks
-            let start_offset = self.archive_chunks_offset + group[0].archive_offset;
-            let chunk_sizes: Vec<u64> = group.iter().map(|c| u64::from(c.archive_size)).collect();
-            let mut chunk_index = 0;
+        // Create groups of chunks so that we can make a single request for all chunks
+        // which are placed in sequence (or close to each other) in archive. Chunks
+        // are grouped in batches of growing size, since the max gap size of a
+        // remote backend is chosen from the stats of the transfers made so far.
+        let mut descriptors = &descriptors[..];
+        let mut batch_size = GROUP_BATCH_MIN_SIZE;
+        while !descriptors.is_empty() {
+            let batch_end = descriptors[0].archive_offset + batch_size;
+            let (batch, rest) = descriptors.split_at(
+                descriptors
+                    .iter()
+                    .position(|chunk| chunk.archive_offset >= batch_end)
+                    .unwrap_or(descriptors.len()),
+            );
+            descriptors = rest;
+            batch_size = cmp::min(batch_size * 2, GROUP_BATCH_MAX_SIZE);
+
+            let grouped_chunks = Self::group_chunks(batch.to_vec(), input.max_gap_size());
+            let groups: Vec<(u64, Vec<u64>)> = grouped_chunks
+                .iter()
+                .map(|group| {
+                    (
+                        self.archive_chunks_offset
+                            + group[0].archive_offset().expect("group starts with chunk"),
+                        group.iter().map(GroupItem::archive_size).collect(),
+                    )
+                })
+                .collect();
+            let items: Vec<GroupItem> = grouped_chunks.into_iter().flatten().collect();
+            let mut item_index = 0;
 
             input
-                .read_in_chunks(start_offset, &chunk_sizes, |archive_data| {
+                .read_chunk_groups(&groups, &mut |archive_data| {
                     // For each chunk read from archive
-                    let chunk_descriptor = &group[chunk_index];
-                    total_read += u64::from(chunk_descriptor.archive_size);
-                    pipe.input(
-                        (
-                            chunk_descriptor.checksum.clone(),
-                            chunk_descriptor.source_size as usize,
-                            archive_data,
-                        ),
-                        move |(checksum, source_size, archive_data): (HashBuf, usize, Vec<u8>)| {
-                            (
-                                Self::decompress_and_verify(
-                                    hash_length,
-                                    chunk_compression,
-                                    &checksum,
-                                    source_size,
-                                    archive_data,
-                                )
-                                .expect("decompression failed"),
-                                checksum,
-                            )
-                        },
-                    );
-
-                    chunk_index += 1;
-
+                    let item = &items[item_index];
+                    item_index += 1;
+                    total_read += item.archive_size();
+                    match item {
+                        GroupItem::Chunk(chunk_descriptor) => {
+                            let mut buf = free_buffers.borrow_mut().pop().unwrap_or_default();
+                            buf.clear();
+                            buf.extend_from_slice(archive_data);
+                            decompress_chunk(chunk_descriptor, ChunkInput::Read(buf))
+                        }
+                        // Data between chunks is not used
+                        GroupItem::Gap(_) => {}
+                    }
                     Ok(())
                 })
                 .expect("read chunks");
@@ -358,3 +609,64 @@ impl ArchiveReader {
         Ok(total_read)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(archive_offset: u64, archive_size: u32) -> archive::ChunkDescriptor {
+        archive::ChunkDescriptor {
+            checksum: vec![],
+            archive_size,
+            archive_offset,
+            source_size: archive_size,
+            compression: None,
+        }
+    }
+
+    fn group_layout(groups: &[Vec<GroupItem>]) -> Vec<Vec<(Option<u64>, u64)>> {
+        groups
+            .iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|item| (item.archive_offset(), item.archive_size()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn group_chunks_in_sequence() {
+        let chunks = vec![descriptor(0, 10), descriptor(10, 5), descriptor(20, 5)];
+        let groups = ArchiveReader::group_chunks(chunks.iter().collect(), 0);
+        assert_eq!(
+            group_layout(&groups),
+            vec![vec![(Some(0), 10), (Some(10), 5)], vec![(Some(20), 5)]]
+        );
+    }
+
+    #[test]
+    fn group_chunks_with_gaps() {
+        let chunks = vec![
+            descriptor(0, 10),
+            descriptor(14, 6),
+            descriptor(25, 5),
+            descriptor(40, 5),
+        ];
+        let groups = ArchiveReader::group_chunks(chunks.iter().collect(), 5);
+        assert_eq!(
+            group_layout(&groups),
+            vec![
+                vec![
+                    (Some(0), 10),
+                    (None, 4),
+                    (Some(14), 6),
+                    (None, 5),
+                    (Some(25), 5)
+                ],
+                vec![(Some(40), 5)]
+            ]
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+
+use crate::archive_reader::ArchiveBackend;
+use crate::errors::*;
+use crate::remote_archive_backend::{RemoteOptions, RemoteReader};
+use crate::stream_archive_backend::StreamReader;
+
+// Function creating a backend for reading the archive at the given location
+pub type BackendFactory = Box<dyn Fn(&str) -> Result<Box<dyn ArchiveBackend>>>;
+
+// Scheme used for locations given as a plain path
+const FILE_SCHEME: &str = "file";
+
+// Location for reading the archive from stdin
+pub const STDIN_LOCATION: &str = "-";
+
+// Resolves archive locations to archive backends by the scheme of the
+// location (e.g. 'http' in 'http://host/file.cba'). Locations without a
+// scheme are opened as local files, and '-' is read as a stream from stdin.
+pub struct BackendRegistry {
+    factories: HashMap<String, BackendFactory>,
+}
+
+// Get the scheme of an URL (lower case), or None if the location is not an URL.
+pub fn url_scheme(location: &str) -> Option<String> {
+    let end = location.find("://")?;
+    let scheme = &location[..end];
+    let mut chars = scheme.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => {}
+        _ => return None,
+    }
+    if chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.') {
+        Some(scheme.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
+// Get the path of a local file location (plain path or file://)
+pub fn file_path(location: &str) -> &str {
+    match url_scheme(location) {
+        Some(scheme) => &location[scheme.len() + 3..],
+        None => location,
+    }
+}
+
+fn open_file(location: &str) -> Result<Box<dyn ArchiveBackend>> {
+    let path = file_path(location);
+    let file = File::open(path).chain_err(|| format!("unable to open {}", path))?;
+    Ok(Box::new(file))
+}
+
+impl BackendRegistry {
+    // Create a registry with the builtin backends for local files (plain
+    // path or file://) and http(s)://.
+    pub fn new() -> Self {
+        Self::with_http(RemoteOptions::default())
+    }
+
+    // Create a registry with the builtin backends, where remote backends for
+    // http(s):// are created using the given options.
+    pub fn with_http(options: RemoteOptions) -> Self {
+        let mut registry = BackendRegistry {
+            factories: HashMap::new(),
+        };
+        registry.register(FILE_SCHEME, open_file);
+        let open_remote = move |location: &str| -> Result<Box<dyn ArchiveBackend>> {
+            Ok(Box::new(RemoteReader::with_options(location, &options)))
+        };
+        registry.register("http", open_remote.clone());
+        registry.register("https", open_remote);
+        registry
+    }
+
+    // Register a backend for the given scheme, replacing any backend already
+    // registered for the scheme. The factory is given the full location.
+    pub fn register<F>(&mut self, scheme: &str, factory: F)
+    where
+        F: Fn(&str) -> Result<Box<dyn ArchiveBackend>> + 'static,
+    {
+        self.factories
+            .insert(scheme.to_ascii_lowercase(), Box::new(factory));
+    }
+
+    // Open a backend for reading the archive at the given location
+    pub fn open(&self, location: &str) -> Result<Box<dyn ArchiveBackend>> {
+        if location == STDIN_LOCATION {
+            return Ok(Box::new(StreamReader::new(io::stdin())));
+        }
+        let scheme = url_scheme(location).unwrap_or_else(|| FILE_SCHEME.to_string());
+        match self.factories.get(&scheme) {
+            Some(factory) => factory(location),
+            None => bail!(
+                "unsupported archive location {} (unknown scheme {})",
+                location,
+                scheme
+            ),
+        }
+    }
+}
+
+impl Default for BackendRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scheme_of_location() {
+        assert_eq!(url_scheme("http://host/file.cba"), Some("http".to_string()));
+        assert_eq!(
+            url_scheme("HTTPS://host/file.cba"),
+            Some("https".to_string())
+        );
+        assert_eq!(url_scheme("file:///tmp/file.cba"), Some("file".to_string()));
+        assert_eq!(
+            url_scheme("s3+v4://bucket/file.cba"),
+            Some("s3+v4".to_string())
+        );
+        assert_eq!(url_scheme("file.cba"), None);
+        assert_eq!(url_scheme("a"), None);
+        assert_eq!(url_scheme("dir/x://file.cba"), None);
+        assert_eq!(url_scheme("://file.cba"), None);
+    }
+
+    #[test]
+    fn path_of_file_location() {
+        assert_eq!(file_path("file:///tmp/file.cba"), "/tmp/file.cba");
+        assert_eq!(file_path("/tmp/file.cba"), "/tmp/file.cba");
+        assert_eq!(file_path("file.cba"), "file.cba");
+    }
+
+    #[test]
+    fn unknown_scheme() {
+        let registry = BackendRegistry::new();
+        assert!(registry.open("ftp://host/file.cba").is_err());
+    }
+
+    #[test]
+    fn registered_scheme() {
+        let mut registry = BackendRegistry::new();
+        registry.register("ftp", |location| bail!("ftp backend opened {}", location));
+        let err = registry.open("FTP://host/file.cba").err().unwrap();
+        assert_eq!(err.to_string(), "ftp backend opened FTP://host/file.cba");
+    }
+}
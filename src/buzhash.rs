@@ -36,11 +36,12 @@ static BUZHASH_TABLE: &'static [u32] = &[
 
 #[derive(Clone)]
 pub struct BuzHash {
+    // Table values of the bytes in window, rotated by the window size
     buf: Vec<u32>,
     index: usize,
     window: usize,
     hash_sum: u32,
-    buzhash_table: Vec<u32>,
+    buzhash_table: [u32; 256],
     window_full: bool,
     last_input: u8,
     repeated_input: usize,
@@ -60,8 +61,12 @@ impl BuzHash {
         }
     }
 
-    fn generate_seeded_table(seed: u32) -> Vec<u32> {
-        BUZHASH_TABLE.iter().map(|x| x ^ seed).collect()
+    fn generate_seeded_table(seed: u32) -> [u32; 256] {
+        let mut table = [0; 256];
+        for (value, table_value) in table.iter_mut().zip(BUZHASH_TABLE.iter()) {
+            *value = table_value ^ seed;
+        }
+        table
     }
 
     // Hash is valid when window is full
@@ -76,7 +81,7 @@ impl BuzHash {
             let shift = self.window - (self.index + 1);
             self.hash_sum ^= in_val.rotate_left(shift as u32);
             self.window_full = self.index >= (self.window - 1);
-            self.buf[self.index] = in_val;
+            self.buf[self.index] = in_val.rotate_left(self.window as u32);
             self.index += 1;
             if self.index >= self.window {
                 self.index = 0;
@@ -84,28 +89,69 @@ impl BuzHash {
         }
     }
 
-    // Push and process a byte
-    pub fn input(&mut self, in_val: u8) {
-        // If the buzhash window is full of the same value then there is no
-        // need pushing another one of the same as it won't change the hash.
-        if in_val == self.last_input {
-            self.repeated_input += 1;
-        } else {
-            self.repeated_input = 0;
-            self.last_input = in_val;
-        }
-        if self.repeated_input < self.window {
-            let in_val = self.buzhash_table[in_val as usize];
+    // Push and process bytes until is_match returns true for the hash sum.
+    // Returns the number of bytes processed if there was a match.
+    // The hash state is kept in locals while processing to let the loop run
+    // without touching self for every byte.
+    #[inline(always)]
+    fn input_until<F>(&mut self, data: &[u8], is_match: F) -> Option<usize>
+    where
+        F: Fn(u32) -> bool,
+    {
+        let window = self.window;
+        let table = &self.buzhash_table;
+        let buf = &mut self.buf[..];
+        let mut hash_sum = self.hash_sum;
+        let mut index = self.index;
+        let mut last_input = self.last_input;
+        let mut repeated_input = self.repeated_input;
+        let mut matched_at = None;
 
-            let out_val = self.buf[self.index];
-            self.hash_sum =
-                self.hash_sum.rotate_left(1) ^ out_val.rotate_left(self.window as u32) ^ in_val;
-            self.buf[self.index] = in_val;
-            self.index += 1;
-            if self.index >= self.window {
-                self.index = 0;
+        for (count, in_val) in data.iter().enumerate() {
+            // If the buzhash window is full of the same value then there is no
+            // need pushing another one of the same as it won't change the hash.
+            if *in_val == last_input {
+                repeated_input += 1;
+            } else {
+                repeated_input = 0;
+                last_input = *in_val;
+            }
+            if repeated_input < window {
+                let in_val = table[*in_val as usize];
+                hash_sum = hash_sum.rotate_left(1) ^ buf[index] ^ in_val;
+                buf[index] = in_val.rotate_left(window as u32);
+                index += 1;
+                if index >= window {
+                    index = 0;
+                }
+            }
+            if is_match(hash_sum) {
+                matched_at = Some(count + 1);
+                break;
             }
         }
+
+        self.hash_sum = hash_sum;
+        self.index = index;
+        self.last_input = last_input;
+        self.repeated_input = repeated_input;
+        matched_at
+    }
+
+    // Push and process a byte
+    pub fn input(&mut self, in_val: u8) {
+        self.input_until(&[in_val], |_| false);
+    }
+
+    // Push and process all given bytes
+    pub fn input_slice(&mut self, data: &[u8]) {
+        self.input_until(data, |_| false);
+    }
+
+    // Push and process bytes until all bits of filter_mask are set in the hash
+    // sum. Returns the number of bytes processed if the filter matched.
+    pub fn input_until_match(&mut self, data: &[u8], filter_mask: u32) -> Option<usize> {
+        self.input_until(data, |hash_sum| hash_sum | filter_mask == hash_sum)
     }
 
     // Get current hash sum
@@ -145,4 +191,38 @@ mod tests {
 
         assert_eq!(sums1[11..], sums2[11..]);
     }
+    #[test]
+    fn slice_input_equals_byte_input() {
+        let data: Vec<u8> = (0..4000u32)
+            .map(|v| {
+                if v % 300 < 40 {
+                    7
+                } else {
+                    (v * 31 % 251) as u8
+                }
+            })
+            .collect();
+        let mut h1 = BuzHash::new(16, 0x10324195);
+        let mut h2 = h1.clone();
+        for v in &data {
+            h1.input(*v);
+        }
+        h2.input_slice(&data[..1000]);
+        h2.input_slice(&data[1000..]);
+        assert_eq!(h1.sum(), h2.sum());
+
+        // A match is reported for the first byte giving a matching hash
+        let mut h1 = BuzHash::new(16, 0x10324195);
+        let mut h2 = h1.clone();
+        let filter_mask = 0b1111;
+        let expected = data.iter().position(|v| {
+            h1.input(*v);
+            h1.sum() | filter_mask == h1.sum()
+        });
+        assert_eq!(
+            h2.input_until_match(&data, filter_mask),
+            expected.map(|i| i + 1)
+        );
+        assert_eq!(h1.sum(), h2.sum());
+    }
 }
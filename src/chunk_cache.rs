@@ -0,0 +1,194 @@
+use protobuf::SingularPtrField;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::archive;
+use crate::archive_reader::ArchiveReader;
+use crate::chunk_dictionary;
+use crate::chunk_dictionary::ChunkDictionary_SignatureType;
+use crate::compression::Compression;
+use crate::errors::*;
+
+// A chunk cache describes where the chunks of an archive are placed in a file
+// which has been unpacked from that archive. Using the cache as seed lets us
+// read chunks directly from the unpacked file without scanning it.
+//
+// The cache file consists of the cache magic, the length (u64) of the unpacked
+// file path, the path itself and then an archive header where each chunk
+// descriptor refers to the (uncompressed) chunk data in the unpacked file.
+pub const CACHE_MAGIC: &[u8; 6] = b"BITAC\0";
+
+pub struct ChunkCache {
+    // Path to the unpacked file described by the cache
+    pub target: PathBuf,
+
+    // Dictionary of the unpacked file
+    pub archive: ArchiveReader,
+}
+
+// Build a chunk cache for the given unpacked target file from the header of
+// the archive it was unpacked from.
+pub fn build_cache(archive_header: &[u8], target: &Path) -> Result<Vec<u8>> {
+    ArchiveReader::verify_pre_header(archive_header)?;
+    let dictionary_size = archive::u64_from_le_slice(
+        &archive_header[archive::FILE_MAGIC.len()..archive::PRE_HEADER_SIZE],
+    ) as usize;
+    let offs = archive::PRE_HEADER_SIZE;
+    let mut dictionary: chunk_dictionary::ChunkDictionary =
+        protobuf::parse_from_bytes(&archive_header[offs..(offs + dictionary_size)])
+            .chain_err(|| "unable to unpack archive header")?;
+
+    // Let each descriptor refer to the first occurrence of the chunk in target
+    let mut target_offsets: Vec<Option<u64>> = vec![None; dictionary.chunk_descriptors.len()];
+    let mut current_offset: u64 = 0;
+    for descriptor_index in dictionary.rebuild_order.iter() {
+        let descriptor_index = *descriptor_index as usize;
+        if target_offsets[descriptor_index].is_none() {
+            target_offsets[descriptor_index] = Some(current_offset);
+        }
+        current_offset += u64::from(dictionary.chunk_descriptors[descriptor_index].source_size);
+    }
+    for (descriptor, target_offset) in dictionary
+        .chunk_descriptors
+        .iter_mut()
+        .zip(target_offsets.into_iter())
+    {
+        descriptor.archive_offset = target_offset.unwrap_or(0);
+        descriptor.archive_size = descriptor.source_size;
+        descriptor.clear_compression();
+    }
+    dictionary.chunk_compression = SingularPtrField::some(Compression::None.into());
+    dictionary.signature_type = ChunkDictionary_SignatureType::NONE;
+    dictionary.clear_zstd_dictionary();
+
+    let target = target.to_str().chain_err(|| "invalid target path")?;
+    let mut cache: Vec<u8> = vec![];
+    cache.extend(CACHE_MAGIC);
+    cache.extend(&(target.len() as u64).to_le_bytes());
+    cache.extend(target.as_bytes());
+    cache.extend(archive::build_header(&dictionary, Some(0), None)?);
+    Ok(cache)
+}
+
+// Check if the given data starts with a chunk cache magic
+pub fn is_cache(pre_header: &[u8]) -> bool {
+    pre_header.len() >= CACHE_MAGIC.len() && &pre_header[0..CACHE_MAGIC.len()] == CACHE_MAGIC
+}
+
+// Read the pre-header and target path of a chunk cache. Input is left at the
+// start of the cache dictionary.
+pub fn read_target<R>(input: &mut R) -> Result<PathBuf>
+where
+    R: Read,
+{
+    let mut pre_header = vec![0; CACHE_MAGIC.len() + 8];
+    input
+        .read_exact(&mut pre_header)
+        .chain_err(|| "unable to read chunk cache")?;
+    if !is_cache(&pre_header) {
+        bail!("invalid chunk cache magic");
+    }
+    let target_size = archive::u64_from_le_slice(&pre_header[CACHE_MAGIC.len()..]) as usize;
+    let mut target = vec![0; target_size];
+    input
+        .read_exact(&mut target)
+        .chain_err(|| "unable to read chunk cache")?;
+    let target = String::from_utf8(target).chain_err(|| "invalid chunk cache target")?;
+    Ok(PathBuf::from(target))
+}
+
+impl ChunkCache {
+    pub fn try_init<R>(input: &mut R) -> Result<Self>
+    where
+        R: Read,
+    {
+        let target = read_target(input)?;
+        let archive = ArchiveReader::try_init(input, &mut Vec::new())
+            .chain_err(|| "unable to read chunk cache dictionary")?;
+
+        Ok(ChunkCache { target, archive })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::Compression;
+    use protobuf::RepeatedField;
+
+    fn descriptor(
+        checksum: u8,
+        source_size: u32,
+        archive_offset: u64,
+    ) -> chunk_dictionary::ChunkDescriptor {
+        let mut descriptor = chunk_dictionary::ChunkDescriptor::new();
+        descriptor.set_checksum(vec![checksum; 64]);
+        descriptor.set_source_size(source_size);
+        descriptor.set_archive_size(source_size / 2);
+        descriptor.set_archive_offset(archive_offset);
+        descriptor
+    }
+
+    // Header of an archive with source layout A B A C
+    fn archive_header() -> Vec<u8> {
+        let mut chunker_params = chunk_dictionary::ChunkerParameters::new();
+        chunker_params.set_chunk_filter_bits(10);
+        chunker_params.set_min_chunk_size(10);
+        chunker_params.set_max_chunk_size(100);
+        chunker_params.set_hash_window_size(8);
+        chunker_params.set_chunk_hash_length(64);
+        let mut dictionary = chunk_dictionary::ChunkDictionary::new();
+        dictionary.set_chunker_params(chunker_params);
+        dictionary.set_chunk_compression(Compression::ZSTD(3).into());
+        dictionary.set_source_total_size(80);
+        dictionary.set_rebuild_order(vec![0, 1, 0, 2]);
+        dictionary.set_chunk_descriptors(RepeatedField::from_vec(vec![
+            descriptor(1, 20, 0),
+            descriptor(2, 30, 10),
+            descriptor(3, 10, 25),
+        ]));
+        archive::build_header(&dictionary, None, None).unwrap()
+    }
+
+    #[test]
+    fn build_and_read_cache() {
+        let cache = build_cache(&archive_header(), Path::new("/some/target.img")).unwrap();
+        assert!(is_cache(&cache));
+        let cache = ChunkCache::try_init(&mut &cache[..]).unwrap();
+        assert_eq!(cache.target, PathBuf::from("/some/target.img"));
+        assert_eq!(cache.archive.chunk_compression, Compression::None);
+        // Chunks refer to their first occurrence in the unpacked target
+        let layout: Vec<(u64, u64, u32, u32)> = cache
+            .archive
+            .chunk_descriptors
+            .iter()
+            .map(|descriptor| {
+                (
+                    descriptor.archive_offset,
+                    descriptor.archive_size as u64,
+                    descriptor.source_size,
+                    descriptor.checksum[0] as u32,
+                )
+            })
+            .collect();
+        assert_eq!(
+            layout,
+            vec![(0, 20, 20, 1), (20, 30, 30, 2), (70, 10, 10, 3)]
+        );
+        assert_eq!(cache.archive.chunk_source_offsets(&[1; 64]), vec![0, 50]);
+    }
+
+    #[test]
+    fn reject_corrupt_cache() {
+        let cache = build_cache(&archive_header(), Path::new("/some/target.img")).unwrap();
+        // Not a cache
+        assert!(ChunkCache::try_init(&mut &archive_header()[..]).is_err());
+        // Truncated cache
+        assert!(ChunkCache::try_init(&mut &cache[..cache.len() - 10]).is_err());
+        // Corrupt dictionary
+        let mut corrupt = cache.clone();
+        let last = corrupt.len() - 80;
+        corrupt[last] ^= 0xff;
+        assert!(ChunkCache::try_init(&mut &corrupt[..]).is_err());
+    }
+}
@@ -28,6 +28,7 @@ pub struct ChunkDescriptor {
     pub archive_size: u32,
     pub archive_offset: u64,
     pub source_size: u32,
+    pub compression: ::protobuf::SingularPtrField<ChunkCompression>,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
@@ -108,10 +109,48 @@ impl ChunkDescriptor {
     pub fn get_source_size(&self) -> u32 {
         self.source_size
     }
+
+    // .chunk_dictionary.ChunkCompression compression = 6;
+
+    pub fn clear_compression(&mut self) {
+        self.compression.clear();
+    }
+
+    pub fn has_compression(&self) -> bool {
+        self.compression.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_compression(&mut self, v: ChunkCompression) {
+        self.compression = ::protobuf::SingularPtrField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_compression(&mut self) -> &mut ChunkCompression {
+        if self.compression.is_none() {
+            self.compression.set_default();
+        }
+        self.compression.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_compression(&mut self) -> ChunkCompression {
+        self.compression.take().unwrap_or_else(|| ChunkCompression::new())
+    }
+
+    pub fn get_compression(&self) -> &ChunkCompression {
+        self.compression.as_ref().unwrap_or_else(|| ChunkCompression::default_instance())
+    }
 }
 
 impl ::protobuf::Message for ChunkDescriptor {
     fn is_initialized(&self) -> bool {
+        for v in &self.compression {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
         true
     }
 
@@ -143,6 +182,9 @@ impl ::protobuf::Message for ChunkDescriptor {
                     let tmp = is.read_uint32()?;
                     self.source_size = tmp;
                 },
+                6 => {
+                    ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.compression)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -167,6 +209,10 @@ impl ::protobuf::Message for ChunkDescriptor {
         if self.source_size != 0 {
             my_size += ::protobuf::rt::value_size(5, self.source_size, ::protobuf::wire_format::WireTypeVarint);
         }
+        if let Some(ref v) = self.compression.as_ref() {
+            let len = v.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -185,6 +231,11 @@ impl ::protobuf::Message for ChunkDescriptor {
         if self.source_size != 0 {
             os.write_uint32(5, self.source_size)?;
         }
+        if let Some(ref v) = self.compression.as_ref() {
+            os.write_tag(6, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -247,6 +298,11 @@ impl ::protobuf::Message for ChunkDescriptor {
                     |m: &ChunkDescriptor| { &m.source_size },
                     |m: &mut ChunkDescriptor| { &mut m.source_size },
                 ));
+                fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<ChunkCompression>>(
+                    "compression",
+                    |m: &ChunkDescriptor| { &m.compression },
+                    |m: &mut ChunkDescriptor| { &mut m.compression },
+                ));
                 ::protobuf::reflect::MessageDescriptor::new::<ChunkDescriptor>(
                     "ChunkDescriptor",
                     fields,
@@ -273,6 +329,7 @@ impl ::protobuf::Clear for ChunkDescriptor {
         self.clear_archive_size();
         self.clear_archive_offset();
         self.clear_source_size();
+        self.clear_compression();
         self.unknown_fields.clear();
     }
 }
@@ -297,6 +354,7 @@ pub struct ChunkerParameters {
     pub max_chunk_size: u32,
     pub hash_window_size: u32,
     pub chunk_hash_length: u32,
+    pub chunking_algorithm: ChunkerParameters_ChunkingAlgorithm,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
@@ -381,6 +439,21 @@ impl ChunkerParameters {
     pub fn get_chunk_hash_length(&self) -> u32 {
         self.chunk_hash_length
     }
+
+    // .chunk_dictionary.ChunkerParameters.ChunkingAlgorithm chunking_algorithm = 6;
+
+    pub fn clear_chunking_algorithm(&mut self) {
+        self.chunking_algorithm = ChunkerParameters_ChunkingAlgorithm::BUZHASH;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_chunking_algorithm(&mut self, v: ChunkerParameters_ChunkingAlgorithm) {
+        self.chunking_algorithm = v;
+    }
+
+    pub fn get_chunking_algorithm(&self) -> ChunkerParameters_ChunkingAlgorithm {
+        self.chunking_algorithm
+    }
 }
 
 impl ::protobuf::Message for ChunkerParameters {
@@ -427,6 +500,9 @@ impl ::protobuf::Message for ChunkerParameters {
                     let tmp = is.read_uint32()?;
                     self.chunk_hash_length = tmp;
                 },
+                6 => {
+                    ::protobuf::rt::read_proto3_enum_with_unknown_fields_into(wire_type, is, &mut self.chunking_algorithm, 6, &mut self.unknown_fields)?
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -454,6 +530,9 @@ impl ::protobuf::Message for ChunkerParameters {
         if self.chunk_hash_length != 0 {
             my_size += ::protobuf::rt::value_size(5, self.chunk_hash_length, ::protobuf::wire_format::WireTypeVarint);
         }
+        if self.chunking_algorithm != ChunkerParameters_ChunkingAlgorithm::BUZHASH {
+            my_size += ::protobuf::rt::enum_size(6, self.chunking_algorithm);
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -475,6 +554,9 @@ impl ::protobuf::Message for ChunkerParameters {
         if self.chunk_hash_length != 0 {
             os.write_uint32(5, self.chunk_hash_length)?;
         }
+        if self.chunking_algorithm != ChunkerParameters_ChunkingAlgorithm::BUZHASH {
+            os.write_enum(6, self.chunking_algorithm.value())?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -542,6 +624,11 @@ impl ::protobuf::Message for ChunkerParameters {
                     |m: &ChunkerParameters| { &m.chunk_hash_length },
                     |m: &mut ChunkerParameters| { &mut m.chunk_hash_length },
                 ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeEnum<ChunkerParameters_ChunkingAlgorithm>>(
+                    "chunking_algorithm",
+                    |m: &ChunkerParameters| { &m.chunking_algorithm },
+                    |m: &mut ChunkerParameters| { &mut m.chunking_algorithm },
+                ));
                 ::protobuf::reflect::MessageDescriptor::new::<ChunkerParameters>(
                     "ChunkerParameters",
                     fields,
@@ -569,6 +656,7 @@ impl ::protobuf::Clear for ChunkerParameters {
         self.clear_max_chunk_size();
         self.clear_hash_window_size();
         self.clear_chunk_hash_length();
+        self.clear_chunking_algorithm();
         self.unknown_fields.clear();
     }
 }
@@ -585,6 +673,64 @@ impl ::protobuf::reflect::ProtobufValue for ChunkerParameters {
     }
 }
 
+#[derive(Clone,PartialEq,Eq,Debug,Hash)]
+pub enum ChunkerParameters_ChunkingAlgorithm {
+    BUZHASH = 0,
+    FIXED_SIZE = 1,
+    FASTCDC = 2,
+}
+
+impl ::protobuf::ProtobufEnum for ChunkerParameters_ChunkingAlgorithm {
+    fn value(&self) -> i32 {
+        *self as i32
+    }
+
+    fn from_i32(value: i32) -> ::std::option::Option<ChunkerParameters_ChunkingAlgorithm> {
+        match value {
+            0 => ::std::option::Option::Some(ChunkerParameters_ChunkingAlgorithm::BUZHASH),
+            1 => ::std::option::Option::Some(ChunkerParameters_ChunkingAlgorithm::FIXED_SIZE),
+            2 => ::std::option::Option::Some(ChunkerParameters_ChunkingAlgorithm::FASTCDC),
+            _ => ::std::option::Option::None
+        }
+    }
+
+    fn values() -> &'static [Self] {
+        static values: &'static [ChunkerParameters_ChunkingAlgorithm] = &[
+            ChunkerParameters_ChunkingAlgorithm::BUZHASH,
+            ChunkerParameters_ChunkingAlgorithm::FIXED_SIZE,
+            ChunkerParameters_ChunkingAlgorithm::FASTCDC,
+        ];
+        values
+    }
+
+    fn enum_descriptor_static() -> &'static ::protobuf::reflect::EnumDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::EnumDescriptor> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const ::protobuf::reflect::EnumDescriptor,
+        };
+        unsafe {
+            descriptor.get(|| {
+                ::protobuf::reflect::EnumDescriptor::new("ChunkerParameters_ChunkingAlgorithm", file_descriptor_proto())
+            })
+        }
+    }
+}
+
+impl ::std::marker::Copy for ChunkerParameters_ChunkingAlgorithm {
+}
+
+impl ::std::default::Default for ChunkerParameters_ChunkingAlgorithm {
+    fn default() -> Self {
+        ChunkerParameters_ChunkingAlgorithm::BUZHASH
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for ChunkerParameters_ChunkingAlgorithm {
+    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef {
+        ::protobuf::reflect::ProtobufValueRef::Enum(self.descriptor())
+    }
+}
+
 #[derive(PartialEq,Clone,Default)]
 pub struct ChunkCompression {
     // message fields
@@ -777,6 +923,8 @@ pub enum ChunkCompression_CompressionType {
     NONE = 0,
     LZMA = 1,
     ZSTD = 2,
+    LZ4 = 3,
+    BROTLI = 4,
 }
 
 impl ::protobuf::ProtobufEnum for ChunkCompression_CompressionType {
@@ -789,6 +937,8 @@ impl ::protobuf::ProtobufEnum for ChunkCompression_CompressionType {
             0 => ::std::option::Option::Some(ChunkCompression_CompressionType::NONE),
             1 => ::std::option::Option::Some(ChunkCompression_CompressionType::LZMA),
             2 => ::std::option::Option::Some(ChunkCompression_CompressionType::ZSTD),
+            3 => ::std::option::Option::Some(ChunkCompression_CompressionType::LZ4),
+            4 => ::std::option::Option::Some(ChunkCompression_CompressionType::BROTLI),
             _ => ::std::option::Option::None
         }
     }
@@ -798,6 +948,8 @@ impl ::protobuf::ProtobufEnum for ChunkCompression_CompressionType {
             ChunkCompression_CompressionType::NONE,
             ChunkCompression_CompressionType::LZMA,
             ChunkCompression_CompressionType::ZSTD,
+            ChunkCompression_CompressionType::LZ4,
+            ChunkCompression_CompressionType::BROTLI,
         ];
         values
     }
@@ -840,6 +992,8 @@ pub struct ChunkDictionary {
     pub chunk_compression: ::protobuf::SingularPtrField<ChunkCompression>,
     pub rebuild_order: ::std::vec::Vec<u32>,
     pub chunk_descriptors: ::protobuf::RepeatedField<ChunkDescriptor>,
+    pub signature_type: ChunkDictionary_SignatureType,
+    pub zstd_dictionary: ::std::vec::Vec<u8>,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
@@ -1032,6 +1186,47 @@ impl ChunkDictionary {
     pub fn get_chunk_descriptors(&self) -> &[ChunkDescriptor] {
         &self.chunk_descriptors
     }
+
+    // .chunk_dictionary.ChunkDictionary.SignatureType signature_type = 8;
+
+    pub fn clear_signature_type(&mut self) {
+        self.signature_type = ChunkDictionary_SignatureType::NONE;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_signature_type(&mut self, v: ChunkDictionary_SignatureType) {
+        self.signature_type = v;
+    }
+
+    pub fn get_signature_type(&self) -> ChunkDictionary_SignatureType {
+        self.signature_type
+    }
+
+    // bytes zstd_dictionary = 9;
+
+    pub fn clear_zstd_dictionary(&mut self) {
+        self.zstd_dictionary.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_zstd_dictionary(&mut self, v: ::std::vec::Vec<u8>) {
+        self.zstd_dictionary = v;
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_zstd_dictionary(&mut self) -> &mut ::std::vec::Vec<u8> {
+        &mut self.zstd_dictionary
+    }
+
+    // Take field
+    pub fn take_zstd_dictionary(&mut self) -> ::std::vec::Vec<u8> {
+        ::std::mem::replace(&mut self.zstd_dictionary, ::std::vec::Vec::new())
+    }
+
+    pub fn get_zstd_dictionary(&self) -> &[u8] {
+        &self.zstd_dictionary
+    }
 }
 
 impl ::protobuf::Message for ChunkDictionary {
@@ -1083,6 +1278,12 @@ impl ::protobuf::Message for ChunkDictionary {
                 7 => {
                     ::protobuf::rt::read_repeated_message_into(wire_type, is, &mut self.chunk_descriptors)?;
                 },
+                8 => {
+                    ::protobuf::rt::read_proto3_enum_with_unknown_fields_into(wire_type, is, &mut self.signature_type, 8, &mut self.unknown_fields)?
+                },
+                9 => {
+                    ::protobuf::rt::read_singular_proto3_bytes_into(wire_type, is, &mut self.zstd_dictionary)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -1119,6 +1320,12 @@ impl ::protobuf::Message for ChunkDictionary {
             let len = value.compute_size();
             my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
         };
+        if self.signature_type != ChunkDictionary_SignatureType::NONE {
+            my_size += ::protobuf::rt::enum_size(8, self.signature_type);
+        }
+        if !self.zstd_dictionary.is_empty() {
+            my_size += ::protobuf::rt::bytes_size(9, &self.zstd_dictionary);
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -1152,6 +1359,12 @@ impl ::protobuf::Message for ChunkDictionary {
             os.write_raw_varint32(v.get_cached_size())?;
             v.write_to_with_cached_sizes(os)?;
         };
+        if self.signature_type != ChunkDictionary_SignatureType::NONE {
+            os.write_enum(8, self.signature_type.value())?;
+        }
+        if !self.zstd_dictionary.is_empty() {
+            os.write_bytes(9, &self.zstd_dictionary)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -1229,6 +1442,16 @@ impl ::protobuf::Message for ChunkDictionary {
                     |m: &ChunkDictionary| { &m.chunk_descriptors },
                     |m: &mut ChunkDictionary| { &mut m.chunk_descriptors },
                 ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeEnum<ChunkDictionary_SignatureType>>(
+                    "signature_type",
+                    |m: &ChunkDictionary| { &m.signature_type },
+                    |m: &mut ChunkDictionary| { &mut m.signature_type },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeBytes>(
+                    "zstd_dictionary",
+                    |m: &ChunkDictionary| { &m.zstd_dictionary },
+                    |m: &mut ChunkDictionary| { &mut m.zstd_dictionary },
+                ));
                 ::protobuf::reflect::MessageDescriptor::new::<ChunkDictionary>(
                     "ChunkDictionary",
                     fields,
@@ -1258,6 +1481,8 @@ impl ::protobuf::Clear for ChunkDictionary {
         self.clear_chunk_compression();
         self.clear_rebuild_order();
         self.clear_chunk_descriptors();
+        self.clear_signature_type();
+        self.clear_zstd_dictionary();
         self.unknown_fields.clear();
     }
 }
@@ -1274,47 +1499,111 @@ impl ::protobuf::reflect::ProtobufValue for ChunkDictionary {
     }
 }
 
+#[derive(Clone,PartialEq,Eq,Debug,Hash)]
+pub enum ChunkDictionary_SignatureType {
+    NONE = 0,
+    ED25519 = 1,
+}
+
+impl ::protobuf::ProtobufEnum for ChunkDictionary_SignatureType {
+    fn value(&self) -> i32 {
+        *self as i32
+    }
+
+    fn from_i32(value: i32) -> ::std::option::Option<ChunkDictionary_SignatureType> {
+        match value {
+            0 => ::std::option::Option::Some(ChunkDictionary_SignatureType::NONE),
+            1 => ::std::option::Option::Some(ChunkDictionary_SignatureType::ED25519),
+            _ => ::std::option::Option::None
+        }
+    }
+
+    fn values() -> &'static [Self] {
+        static values: &'static [ChunkDictionary_SignatureType] = &[
+            ChunkDictionary_SignatureType::NONE,
+            ChunkDictionary_SignatureType::ED25519,
+        ];
+        values
+    }
+
+    fn enum_descriptor_static() -> &'static ::protobuf::reflect::EnumDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::EnumDescriptor> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const ::protobuf::reflect::EnumDescriptor,
+        };
+        unsafe {
+            descriptor.get(|| {
+                ::protobuf::reflect::EnumDescriptor::new("ChunkDictionary_SignatureType", file_descriptor_proto())
+            })
+        }
+    }
+}
+
+impl ::std::marker::Copy for ChunkDictionary_SignatureType {
+}
+
+impl ::std::default::Default for ChunkDictionary_SignatureType {
+    fn default() -> Self {
+        ChunkDictionary_SignatureType::NONE
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for ChunkDictionary_SignatureType {
+    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef {
+        ::protobuf::reflect::ProtobufValueRef::Enum(self.descriptor())
+    }
+}
+
 static file_descriptor_proto_data: &'static [u8] = b"\
-    \n\x1cproto/chunk_dictionary.proto\x12\x10chunk_dictionary\"\x98\x01\n\
+    \n\x1cproto/chunk_dictionary.proto\x12\x10chunk_dictionary\"\xde\x01\n\
     \x0fChunkDescriptor\x12\x1a\n\x08checksum\x18\x01\x20\x01(\x0cR\x08check\
     sum\x12!\n\x0carchive_size\x18\x03\x20\x01(\rR\x0barchiveSize\x12%\n\x0e\
     archive_offset\x18\x04\x20\x01(\x04R\rarchiveOffset\x12\x1f\n\x0bsource_\
-    size\x18\x05\x20\x01(\rR\nsourceSize\"\xe1\x01\n\x11ChunkerParameters\
-    \x12*\n\x11chunk_filter_bits\x18\x01\x20\x01(\rR\x0fchunkFilterBits\x12$\
-    \n\x0emin_chunk_size\x18\x02\x20\x01(\rR\x0cminChunkSize\x12$\n\x0emax_c\
-    hunk_size\x18\x03\x20\x01(\rR\x0cmaxChunkSize\x12(\n\x10hash_window_size\
-    \x18\x04\x20\x01(\rR\x0ehashWindowSize\x12*\n\x11chunk_hash_length\x18\
-    \x05\x20\x01(\rR\x0fchunkHashLength\"\xc6\x01\n\x10ChunkCompression\x12T\
-    \n\x0bcompression\x18\x02\x20\x01(\x0e22.chunk_dictionary.ChunkCompressi\
-    on.CompressionTypeR\x0bcompression\x12+\n\x11compression_level\x18\x03\
-    \x20\x01(\rR\x10compressionLevel\"/\n\x0fCompressionType\x12\x08\n\x04NO\
-    NE\x10\0\x12\x08\n\x04LZMA\x10\x01\x12\x08\n\x04ZSTD\x10\x02\"\xa9\x03\n\
-    \x0fChunkDictionary\x12/\n\x13application_version\x18\x01\x20\x01(\tR\
-    \x12applicationVersion\x12'\n\x0fsource_checksum\x18\x02\x20\x01(\x0cR\
-    \x0esourceChecksum\x12*\n\x11source_total_size\x18\x03\x20\x01(\x04R\x0f\
-    sourceTotalSize\x12J\n\x0echunker_params\x18\x04\x20\x01(\x0b2#.chunk_di\
-    ctionary.ChunkerParametersR\rchunkerParams\x12O\n\x11chunk_compression\
-    \x18\x05\x20\x01(\x0b2\".chunk_dictionary.ChunkCompressionR\x10chunkComp\
-    ression\x12#\n\rrebuild_order\x18\x06\x20\x03(\rR\x0crebuildOrder\x12N\n\
-    \x11chunk_descriptors\x18\x07\x20\x03(\x0b2!.chunk_dictionary.ChunkDescr\
-    iptorR\x10chunkDescriptorsJ\x8e\x10\n\x06\x12\x04\0\0<\x01\n\x08\n\x01\
-    \x0c\x12\x03\0\0\x12\n\x08\n\x01\x02\x12\x03\x02\x08\x18\n\n\n\x02\x04\0\
-    \x12\x04\x04\0\x10\x01\n\n\n\x03\x04\0\x01\x12\x03\x04\x08\x17\n+\n\x04\
-    \x04\0\x02\0\x12\x03\x07\x04\x17\x1a\x1e\x20Hash\x20of\x20(uncompressed)\
-    \x20chunk\n\n\r\n\x05\x04\0\x02\0\x04\x12\x04\x07\x04\x05\x01\n\x0c\n\
-    \x05\x04\0\x02\0\x05\x12\x03\x07\x04\t\n\x0c\n\x05\x04\0\x02\0\x01\x12\
-    \x03\x07\n\x12\n\x0c\n\x05\x04\0\x02\0\x03\x12\x03\x07\x15\x16\nq\n\x04\
-    \x04\0\x02\x01\x12\x03\x0b\x04\x1c\x1ad\x20Chunk\x20data\x20placement\
-    \x20in\x20archive.\n\x20If\x20the\x20archive_size\x20=\x20source_size\
-    \x20then\x20the\x20chunk\x20is\x20uncompresed.\n\n\r\n\x05\x04\0\x02\x01\
-    \x04\x12\x04\x0b\x04\x07\x17\n\x0c\n\x05\x04\0\x02\x01\x05\x12\x03\x0b\
-    \x04\n\n\x0c\n\x05\x04\0\x02\x01\x01\x12\x03\x0b\x0b\x17\n\x0c\n\x05\x04\
-    \0\x02\x01\x03\x12\x03\x0b\x1a\x1b\n\x0b\n\x04\x04\0\x02\x02\x12\x03\x0c\
-    \x04\x1e\n\r\n\x05\x04\0\x02\x02\x04\x12\x04\x0c\x04\x0b\x1c\n\x0c\n\x05\
-    \x04\0\x02\x02\x05\x12\x03\x0c\x04\n\n\x0c\n\x05\x04\0\x02\x02\x01\x12\
-    \x03\x0c\x0b\x19\n\x0c\n\x05\x04\0\x02\x02\x03\x12\x03\x0c\x1c\x1d\n.\n\
-    \x04\x04\0\x02\x03\x12\x03\x0f\x04\x1b\x1a!\x20Size\x20of\x20uncompresse\
-    d\x20chunk\x20data\n\n\r\n\x05\x04\0\x02\x03\x04\x12\x04\x0f\x04\x0c\x1e\
+    size\x18\x05\x20\x01(\rR\nsourceSize\x12D\n\x0bcompression\x18\x06\x20\
+    \x01(\x0b2\".chunk_dictionary.ChunkCompressionR\x0bcompression\"\x86\x03\
+    \n\x11ChunkerParameters\x12*\n\x11chunk_filter_bits\x18\x01\x20\x01(\rR\
+    \x0fchunkFilterBits\x12$\n\x0emin_chunk_size\x18\x02\x20\x01(\rR\x0cminC\
+    hunkSize\x12$\n\x0emax_chunk_size\x18\x03\x20\x01(\rR\x0cmaxChunkSize\
+    \x12(\n\x10hash_window_size\x18\x04\x20\x01(\rR\x0ehashWindowSize\x12*\n\
+    \x11chunk_hash_length\x18\x05\x20\x01(\rR\x0fchunkHashLength\x12d\n\x12c\
+    hunking_algorithm\x18\x06\x20\x01(\x0e25.chunk_dictionary.ChunkerParamet\
+    ers.ChunkingAlgorithmR\x11chunkingAlgorithm\"=\n\x11ChunkingAlgorithm\
+    \x12\x0b\n\x07BUZHASH\x10\0\x12\x0e\n\nFIXED_SIZE\x10\x01\x12\x0b\n\x07F\
+    ASTCDC\x10\x02\"\xdb\x01\n\x10ChunkCompression\x12T\n\x0bcompression\x18\
+    \x02\x20\x01(\x0e22.chunk_dictionary.ChunkCompression.CompressionTypeR\
+    \x0bcompression\x12+\n\x11compression_level\x18\x03\x20\x01(\rR\x10compr\
+    essionLevel\"D\n\x0fCompressionType\x12\x08\n\x04NONE\x10\0\x12\x08\n\
+    \x04LZMA\x10\x01\x12\x08\n\x04ZSTD\x10\x02\x12\x07\n\x03LZ4\x10\x03\x12\
+    \n\n\x06BROTLI\x10\x04\"\xd2\x04\n\x0fChunkDictionary\x12/\n\x13applicat\
+    ion_version\x18\x01\x20\x01(\tR\x12applicationVersion\x12'\n\x0fsource_c\
+    hecksum\x18\x02\x20\x01(\x0cR\x0esourceChecksum\x12*\n\x11source_total_s\
+    ize\x18\x03\x20\x01(\x04R\x0fsourceTotalSize\x12J\n\x0echunker_params\
+    \x18\x04\x20\x01(\x0b2#.chunk_dictionary.ChunkerParametersR\rchunkerPara\
+    ms\x12O\n\x11chunk_compression\x18\x05\x20\x01(\x0b2\".chunk_dictionary.\
+    ChunkCompressionR\x10chunkCompression\x12#\n\rrebuild_order\x18\x06\x20\
+    \x03(\rR\x0crebuildOrder\x12N\n\x11chunk_descriptors\x18\x07\x20\x03(\
+    \x0b2!.chunk_dictionary.ChunkDescriptorR\x10chunkDescriptors\x12V\n\x0es\
+    ignature_type\x18\x08\x20\x01(\x0e2/.chunk_dictionary.ChunkDictionary.Si\
+    gnatureTypeR\rsignatureType\x12'\n\x0fzstd_dictionary\x18\t\x20\x01(\x0c\
+    R\x0ezstdDictionary\"&\n\rSignatureType\x12\x08\n\x04NONE\x10\0\x12\x0b\
+    \n\x07ED25519\x10\x01J\x8e\x10\n\x06\x12\x04\0\0<\x01\n\x08\n\x01\x0c\
+    \x12\x03\0\0\x12\n\x08\n\x01\x02\x12\x03\x02\x08\x18\n\n\n\x02\x04\0\x12\
+    \x04\x04\0\x10\x01\n\n\n\x03\x04\0\x01\x12\x03\x04\x08\x17\n+\n\x04\x04\
+    \0\x02\0\x12\x03\x07\x04\x17\x1a\x1e\x20Hash\x20of\x20(uncompressed)\x20\
+    chunk\n\n\r\n\x05\x04\0\x02\0\x04\x12\x04\x07\x04\x05\x01\n\x0c\n\x05\
+    \x04\0\x02\0\x05\x12\x03\x07\x04\t\n\x0c\n\x05\x04\0\x02\0\x01\x12\x03\
+    \x07\n\x12\n\x0c\n\x05\x04\0\x02\0\x03\x12\x03\x07\x15\x16\nq\n\x04\x04\
+    \0\x02\x01\x12\x03\x0b\x04\x1c\x1ad\x20Chunk\x20data\x20placement\x20in\
+    \x20archive.\n\x20If\x20the\x20archive_size\x20=\x20source_size\x20then\
+    \x20the\x20chunk\x20is\x20uncompresed.\n\n\r\n\x05\x04\0\x02\x01\x04\x12\
+    \x04\x0b\x04\x07\x17\n\x0c\n\x05\x04\0\x02\x01\x05\x12\x03\x0b\x04\n\n\
+    \x0c\n\x05\x04\0\x02\x01\x01\x12\x03\x0b\x0b\x17\n\x0c\n\x05\x04\0\x02\
+    \x01\x03\x12\x03\x0b\x1a\x1b\n\x0b\n\x04\x04\0\x02\x02\x12\x03\x0c\x04\
+    \x1e\n\r\n\x05\x04\0\x02\x02\x04\x12\x04\x0c\x04\x0b\x1c\n\x0c\n\x05\x04\
+    \0\x02\x02\x05\x12\x03\x0c\x04\n\n\x0c\n\x05\x04\0\x02\x02\x01\x12\x03\
+    \x0c\x0b\x19\n\x0c\n\x05\x04\0\x02\x02\x03\x12\x03\x0c\x1c\x1d\n.\n\x04\
+    \x04\0\x02\x03\x12\x03\x0f\x04\x1b\x1a!\x20Size\x20of\x20uncompressed\
+    \x20chunk\x20data\n\n\r\n\x05\x04\0\x02\x03\x04\x12\x04\x0f\x04\x0c\x1e\
     \n\x0c\n\x05\x04\0\x02\x03\x05\x12\x03\x0f\x04\n\n\x0c\n\x05\x04\0\x02\
     \x03\x01\x12\x03\x0f\x0b\x16\n\x0c\n\x05\x04\0\x02\x03\x03\x12\x03\x0f\
     \x19\x1a\n\n\n\x02\x04\x01\x12\x04\x12\0\x19\x01\n\n\n\x03\x04\x01\x01\
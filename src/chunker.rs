@@ -1,6 +1,9 @@
 use crate::buzhash::BuzHash;
+use crate::fastcdc::FastCdc;
+use std::cmp;
 use std::io;
 use std::io::prelude::*;
+use std::vec;
 
 const CHUNKER_BUF_SIZE: usize = 1024 * 1024;
 
@@ -30,8 +33,21 @@ pub struct Chunk {
     pub data: Vec<u8>,
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChunkingAlgorithm {
+    // Content defined chunk boundaries using a rolling hash
+    BuzHash,
+    // Chunk boundaries at fixed offsets, every chunk is max_chunk_size long
+    // (except for the last one)
+    FixedSize,
+    // Content defined chunk boundaries using a gear hash with normalized
+    // chunk sizes (FastCDC)
+    FastCdc,
+}
+
+#[derive(Clone, PartialEq)]
 pub struct ChunkerParams {
+    pub algorithm: ChunkingAlgorithm,
     pub filter_bits: u32,
     pub min_chunk_size: usize,
     pub max_chunk_size: usize,
@@ -48,6 +64,7 @@ impl ChunkerParams {
         buzhash_seed: u32,
     ) -> Self {
         ChunkerParams {
+            algorithm: ChunkingAlgorithm::BuzHash,
             filter_bits: chunk_filter_bits,
             min_chunk_size,
             max_chunk_size,
@@ -56,6 +73,32 @@ impl ChunkerParams {
         }
     }
 
+    pub fn new_fixed_size(chunk_size: usize) -> Self {
+        ChunkerParams {
+            algorithm: ChunkingAlgorithm::FixedSize,
+            filter_bits: 0,
+            min_chunk_size: chunk_size,
+            max_chunk_size: chunk_size,
+            buzhash_window_size: 0,
+            buzhash_seed: 0,
+        }
+    }
+
+    pub fn new_fastcdc(
+        chunk_filter_bits: u32,
+        min_chunk_size: usize,
+        max_chunk_size: usize,
+    ) -> Self {
+        ChunkerParams {
+            algorithm: ChunkingAlgorithm::FastCdc,
+            filter_bits: chunk_filter_bits,
+            min_chunk_size,
+            max_chunk_size,
+            buzhash_window_size: 0,
+            buzhash_seed: 0,
+        }
+    }
+
     pub fn filter_mask(&self) -> u32 {
         (!0 as u32) >> (32 - self.filter_bits)
     }
@@ -69,17 +112,23 @@ pub struct Chunker<'a, T>
 where
     T: Read,
 {
+    algorithm: ChunkingAlgorithm,
     buzhash: BuzHash,
+    fastcdc: FastCdc,
     filter_mask: u32,
     min_chunk_size: usize,
     max_chunk_size: usize,
     source_buf: Vec<u8>,
+    // Start of the current chunk in source_buf
+    buf_start: usize,
     source: &'a mut T,
     buzhash_input_limit: usize,
     source_index: u64,
     buf_index: usize,
     chunk_start: u64,
     last_chunk_size: usize,
+    // Chunk boundaries (end offset of each chunk) known in advance
+    boundaries: Option<vec::IntoIter<u64>>,
 }
 
 impl<'a, T> Chunker<'a, T>
@@ -94,34 +143,76 @@ where
             0
         };
 
+        let filter_mask = match params.algorithm {
+            ChunkingAlgorithm::BuzHash => params.filter_mask(),
+            ChunkingAlgorithm::FixedSize | ChunkingAlgorithm::FastCdc => 0,
+        };
+
         Chunker {
-            filter_mask: params.filter_mask(),
+            algorithm: params.algorithm,
+            filter_mask,
             min_chunk_size: params.min_chunk_size,
             max_chunk_size: params.max_chunk_size,
             buzhash: BuzHash::new(params.buzhash_window_size, params.buzhash_seed),
+            fastcdc: FastCdc::new(
+                params.chunk_target_average() as usize,
+                params.min_chunk_size,
+                params.max_chunk_size,
+            ),
             source_buf: Vec::new(),
+            buf_start: 0,
             source,
             buzhash_input_limit,
             source_index: 0,
             buf_index: 0,
             chunk_start: 0,
             last_chunk_size: 0,
+            boundaries: None,
         }
     }
 
+    // Create a chunker which splits the source at the given chunk boundaries
+    // (end offset of each chunk) instead of scanning for them. Used when the
+    // boundaries has already been found, like by the parallel chunker.
+    pub fn with_boundaries(params: ChunkerParams, source: &'a mut T, boundaries: Vec<u64>) -> Self {
+        let mut chunker = Self::new(params, source);
+        chunker.boundaries = Some(boundaries.into_iter());
+        chunker
+    }
+
+    // Read more data from source into the buffer. Data of chunks already
+    // returned is removed from the buffer first. Removing it here, rather than
+    // after every chunk, avoids moving the buffered data for every chunk.
+    fn fill_buf(&mut self, count: usize) -> io::Result<usize> {
+        if self.buf_start > 0 {
+            self.source_buf.drain(..self.buf_start);
+            self.buf_start = 0;
+        }
+        append_to_buf(self.source, &mut self.source_buf, count)
+    }
+
     // Scan source for chunks.
     // Each call returns a chunk with offset or None if EOF was reached.
     pub fn scan<'b>(&'b mut self) -> io::Result<Option<(u64, &'b [u8])>> {
         if self.last_chunk_size > 0 {
-            self.source_buf.drain(..self.last_chunk_size);
+            self.buf_start += self.last_chunk_size;
             self.last_chunk_size = 0;
             self.buf_index = 0;
         }
 
+        if self.boundaries.is_some() {
+            return self.scan_boundaries();
+        }
+        match self.algorithm {
+            ChunkingAlgorithm::FixedSize => return self.scan_fixed_size(),
+            ChunkingAlgorithm::FastCdc => return self.scan_fastcdc(),
+            ChunkingAlgorithm::BuzHash => {}
+        }
+
         loop {
-            if self.buf_index >= self.source_buf.len() {
+            if self.buf_index >= self.source_buf.len() - self.buf_start {
                 // Fill buffer from source input
-                let rc = append_to_buf(self.source, &mut self.source_buf, CHUNKER_BUF_SIZE)?;
+                let rc = self.fill_buf(CHUNKER_BUF_SIZE)?;
                 if rc == 0 {
                     // EOF
                     if !self.source_buf.is_empty() {
@@ -139,12 +230,15 @@ where
                 }
             }
 
+            // Buffered data from the start of the current chunk
+            let buf = &self.source_buf[self.buf_start..];
+
             // Skip past the minimum chunk size to minimize the number of hash inputs
             let mut buf_index =
                 if self.buf_index < self.buzhash_input_limit && self.buzhash_input_limit > 0 {
                     let skip_to = self.buzhash_input_limit - 1;
-                    if skip_to >= self.source_buf.len() {
-                        self.source_buf.len()
+                    if skip_to >= buf.len() {
+                        buf.len()
                     } else {
                         skip_to
                     }
@@ -152,29 +246,36 @@ where
                     self.buf_index
                 };
 
-            let mut got_chunk = false;
-            if self.min_chunk_size > 0 {
-                for val in self.source_buf[buf_index..].iter() {
-                    buf_index += 1;
-                    self.buzhash.input(*val);
-                    if buf_index >= (self.min_chunk_size - 1) {
-                        break;
-                    }
-                }
+            // Input (at least one byte) up to the minimum chunk size without
+            // looking for a boundary
+            if self.min_chunk_size > 0 && buf_index < buf.len() {
+                let end = cmp::min(buf.len(), cmp::max(self.min_chunk_size - 1, buf_index + 1));
+                self.buzhash.input_slice(&buf[buf_index..end]);
+                buf_index = end;
             }
 
-            // Scan for chunk boundary
-            for val in self.source_buf[buf_index..].iter() {
-                buf_index += 1;
-                self.buzhash.input(*val);
-
-                got_chunk = buf_index >= self.max_chunk_size;
-                if !got_chunk {
-                    let hash = self.buzhash.sum();
-                    got_chunk = hash | self.filter_mask == hash;
+            // Scan for chunk boundary up to the maximum chunk size
+            let mut got_chunk = false;
+            let scan_end = cmp::min(
+                buf.len(),
+                cmp::max(buf_index, self.max_chunk_size.saturating_sub(1)),
+            );
+            match self
+                .buzhash
+                .input_until_match(&buf[buf_index..scan_end], self.filter_mask)
+            {
+                Some(count) => {
+                    buf_index += count;
+                    got_chunk = true;
                 }
-                if got_chunk {
-                    break;
+                None => {
+                    buf_index = scan_end;
+                    if buf_index < buf.len() {
+                        // Maximum chunk size reached
+                        self.buzhash.input(buf[buf_index]);
+                        buf_index += 1;
+                        got_chunk = true;
+                    }
                 }
             }
             self.source_index += (buf_index - self.buf_index) as u64;
@@ -184,17 +285,96 @@ where
                 self.last_chunk_size = self.buf_index;
                 let chunk_start = self.chunk_start;
                 self.chunk_start = self.source_index;
-                return Ok(Some((chunk_start, &self.source_buf[..self.buf_index])));
+                return Ok(Some((
+                    chunk_start,
+                    &self.source_buf[self.buf_start..self.buf_start + self.buf_index],
+                )));
             }
         }
     }
+
+    // Scan source for the next fixed size chunk.
+    fn scan_fixed_size<'b>(&'b mut self) -> io::Result<Option<(u64, &'b [u8])>> {
+        let buffered = self.source_buf.len() - self.buf_start;
+        if buffered < self.max_chunk_size {
+            self.fill_buf(self.max_chunk_size - buffered)?;
+        }
+        let buf = &self.source_buf[self.buf_start..];
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        let chunk_size = cmp::min(buf.len(), self.max_chunk_size);
+        let chunk_start = self.chunk_start;
+        self.last_chunk_size = chunk_size;
+        self.source_index += chunk_size as u64;
+        self.chunk_start = self.source_index;
+        Ok(Some((chunk_start, &buf[..chunk_size])))
+    }
+
+    // Get the next chunk from source using the known chunk boundaries.
+    fn scan_boundaries<'b>(&'b mut self) -> io::Result<Option<(u64, &'b [u8])>> {
+        let chunk_end = match self.boundaries.as_mut().and_then(|b| b.next()) {
+            Some(chunk_end) => chunk_end,
+            None => return Ok(None),
+        };
+        let chunk_size = (chunk_end - self.chunk_start) as usize;
+        let buffered = self.source_buf.len() - self.buf_start;
+        if buffered < chunk_size {
+            self.fill_buf(cmp::max(chunk_size - buffered, CHUNKER_BUF_SIZE))?;
+        }
+        let buf = &self.source_buf[self.buf_start..];
+        if buf.len() < chunk_size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "source ended before last chunk boundary",
+            ));
+        }
+        let chunk_start = self.chunk_start;
+        self.last_chunk_size = chunk_size;
+        self.source_index += chunk_size as u64;
+        self.chunk_start = self.source_index;
+        Ok(Some((chunk_start, &buf[..chunk_size])))
+    }
+
+    // Scan source for the next chunk using FastCDC.
+    fn scan_fastcdc<'b>(&'b mut self) -> io::Result<Option<(u64, &'b [u8])>> {
+        let chunk_size = loop {
+            let buf = &self.source_buf[self.buf_start..];
+            if let Some(chunk_size) = self.fastcdc.find_boundary(buf, self.buf_index) {
+                break chunk_size;
+            }
+            self.buf_index = buf.len();
+
+            // Fill buffer from source input
+            let rc = self.fill_buf(CHUNKER_BUF_SIZE)?;
+            if rc == 0 {
+                // EOF
+                if self.source_buf.is_empty() {
+                    return Ok(None);
+                }
+                break self.source_buf.len();
+            }
+        };
+        self.fastcdc.reset();
+        let chunk_start = self.chunk_start;
+        self.last_chunk_size = chunk_size;
+        self.source_index += chunk_size as u64;
+        self.chunk_start = self.source_index;
+        Ok(Some((
+            chunk_start,
+            &self.source_buf[self.buf_start..self.buf_start + chunk_size],
+        )))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::Chunker;
     use super::ChunkerParams;
+    use super::CHUNKER_BUF_SIZE;
     use crate::archive::BUZHASH_SEED;
+    use crate::buzhash::BuzHash;
+    use std::cmp;
 
     #[test]
     fn zero_data() {
@@ -364,4 +544,168 @@ mod tests {
         }
         assert_eq!(expected_chunk_offsets[..], chunk_offsets[..]);
     }
+    #[test]
+    fn fixed_size() {
+        let src = (0..1000)
+            .map(|v: u64| (v & 0xff) as u8)
+            .collect::<Vec<u8>>();
+        let mut src: &[u8] = &src;
+        let mut chunker = Chunker::new(ChunkerParams::new_fixed_size(300), &mut src);
+        let mut chunks: Vec<(u64, usize)> = Vec::new();
+        while let Some((offset, data)) = chunker.scan().expect("scan") {
+            assert_eq!(data[0], (offset & 0xff) as u8);
+            chunks.push((offset, data.len()));
+        }
+        assert_eq!(chunks, [(0, 300), (300, 300), (600, 300), (900, 100)]);
+    }
+    // Chunk offsets as given by the original byte by byte buzhash scan, which
+    // the chunker must give identical chunk boundaries as.
+    fn reference_chunk_offsets(src: &[u8], params: &ChunkerParams) -> Vec<u64> {
+        let mut buzhash = BuzHash::new(params.buzhash_window_size, params.buzhash_seed);
+        let input_limit = params
+            .min_chunk_size
+            .saturating_sub(params.buzhash_window_size);
+        let mut offsets = Vec::new();
+        let mut read_offset = 0;
+        let mut buf_index = 0;
+        let mut source_index = 0;
+        let mut chunk_start = 0;
+        loop {
+            // Data read from source from the start of the current chunk
+            let mut buf = &src[chunk_start as usize..read_offset];
+            if buf_index >= buf.len() {
+                let read_size = cmp::min(CHUNKER_BUF_SIZE, src.len() - read_offset);
+                read_offset += read_size;
+                buf = &src[chunk_start as usize..read_offset];
+                if read_size == 0 {
+                    if !buf.is_empty() {
+                        offsets.push(chunk_start);
+                    }
+                    return offsets;
+                }
+                while !buzhash.valid() && buf_index < buf.len() {
+                    buzhash.init(buf[buf_index]);
+                    buf_index += 1;
+                    source_index += 1;
+                }
+            }
+            let mut index = if buf_index < input_limit && input_limit > 0 {
+                cmp::min(input_limit - 1, buf.len())
+            } else {
+                buf_index
+            };
+            let mut got_chunk = false;
+            if params.min_chunk_size > 0 {
+                for val in buf[index..].iter() {
+                    index += 1;
+                    buzhash.input(*val);
+                    if index >= params.min_chunk_size - 1 {
+                        break;
+                    }
+                }
+            }
+            for val in buf[index..].iter() {
+                index += 1;
+                buzhash.input(*val);
+                let hash = buzhash.sum();
+                got_chunk = index >= params.max_chunk_size || hash | params.filter_mask() == hash;
+                if got_chunk {
+                    break;
+                }
+            }
+            source_index += (index - buf_index) as u64;
+            buf_index = index;
+            if got_chunk {
+                offsets.push(chunk_start);
+                chunk_start = source_index;
+                buf_index = 0;
+            }
+        }
+    }
+    #[test]
+    fn scan_equals_reference_scan() {
+        // Random data with some runs of repeated bytes, larger than the chunker
+        // buffer to also cover boundaries found across buffer refills.
+        let mut seed: u64 = 0xa3;
+        let src = (0..(CHUNKER_BUF_SIZE * 5 / 2))
+            .map(|v| {
+                seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+                if v % 50_000 < 3000 {
+                    0
+                } else {
+                    (seed >> 56) as u8
+                }
+            })
+            .collect::<Vec<u8>>();
+        for params in &[
+            ChunkerParams::new(12, 2048, 65536, 16, BUZHASH_SEED),
+            ChunkerParams::new(14, 64, 1024, 20, BUZHASH_SEED),
+            ChunkerParams::new(5, 0, 200_000, 8, BUZHASH_SEED),
+        ] {
+            let mut src_reader: &[u8] = &src;
+            let mut chunker = Chunker::new(params.clone(), &mut src_reader);
+            let mut chunk_offsets: Vec<u64> = Vec::new();
+            while let Some((offset, _data)) = chunker.scan().expect("scan") {
+                chunk_offsets.push(offset);
+            }
+            assert_eq!(chunk_offsets, reference_chunk_offsets(&src, params));
+        }
+    }
+    fn fastcdc_chunks(src: &[u8], params: ChunkerParams) -> Vec<(u64, usize)> {
+        let mut src: &[u8] = src;
+        let mut chunker = Chunker::new(params, &mut src);
+        let mut chunks: Vec<(u64, usize)> = Vec::new();
+        while let Some((offset, data)) = chunker.scan().expect("scan") {
+            chunks.push((offset, data.len()));
+        }
+        chunks
+    }
+    #[test]
+    fn fastcdc_chunk_sizes() {
+        let mut seed: u64 = 0x1f23_ab13;
+        let src = (0..200_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+                (seed >> 56) as u8
+            })
+            .collect::<Vec<u8>>();
+        let chunks = fastcdc_chunks(&src, ChunkerParams::new_fastcdc(9, 256, 4096));
+        assert!(chunks.len() > 100);
+
+        // Chunks are in sequence and within size limits, except for the last one
+        let mut offset = 0;
+        for (index, (chunk_offset, chunk_size)) in chunks.iter().enumerate() {
+            assert_eq!(*chunk_offset, offset);
+            assert!(*chunk_size <= 4096);
+            if index < chunks.len() - 1 {
+                assert!(*chunk_size >= 256);
+            }
+            offset += *chunk_size as u64;
+        }
+        assert_eq!(offset, src.len() as u64);
+
+        // Boundaries are found again after data is inserted in source
+        let mut modified_src = vec![0xaa; 100];
+        modified_src.extend(&src);
+        let modified_chunks =
+            fastcdc_chunks(&modified_src, ChunkerParams::new_fastcdc(9, 256, 4096));
+        let shifted_chunks: Vec<(u64, usize)> = chunks[2..]
+            .iter()
+            .map(|(offset, size)| (offset + 100, *size))
+            .collect();
+        assert!(modified_chunks.ends_with(&shifted_chunks[..]));
+    }
+    #[test]
+    fn fastcdc_small_source() {
+        let src = vec![0x1f, 0x55, 0x39, 0x5e, 0xfa];
+        assert_eq!(
+            fastcdc_chunks(&src, ChunkerParams::new_fastcdc(5, 10, 40)),
+            [(0, 5)]
+        );
+        assert_eq!(
+            fastcdc_chunks(&src, ChunkerParams::new_fastcdc(5, 0, 2)),
+            [(0, 2), (2, 2), (4, 1)]
+        );
+        assert!(fastcdc_chunks(&[], ChunkerParams::new_fastcdc(5, 0, 40)).is_empty());
+    }
 }
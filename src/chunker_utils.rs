@@ -5,6 +5,7 @@ use std::io::prelude::*;
 use threadpool::ThreadPool;
 
 use crate::chunker::*;
+use crate::compression::Compression;
 use crate::para_pipe::ParaPipe;
 
 pub type HashBuf = Vec<u8>;
@@ -22,6 +23,9 @@ pub struct CompressedChunk {
     pub offset: u64,
     pub data: Vec<u8>,
     pub cdata: Vec<u8>,
+
+    // Compression used for cdata
+    pub compression: Compression,
 }
 
 #[derive(Debug, Clone)]
@@ -128,22 +132,27 @@ pub fn unique_compressed_chunks<T, F, C, H>(
 where
     T: Read,
     F: FnMut(CompressedChunk),
-    C: Fn(&[u8]) -> Vec<u8> + Send + 'static + Copy,
+    C: Fn(&[u8]) -> (Compression, Vec<u8>) + Send + 'static + Clone,
     H: Fn(&[u8]) -> Vec<u8> + Send + 'static + Copy,
 {
     let mut pipe = ParaPipe::new_output(pool, chunk_callback);
 
     let (file_size, file_hash, chunks) =
         unique_chunks(chunker, hash_chunk, &pool, hash_input, |hashed_chunk| {
-            pipe.input(hashed_chunk, move |hashed_chunk: HashedChunk| {
-                let cdata = compress_chunk(&hashed_chunk.data);
-                CompressedChunk {
-                    hash: hashed_chunk.hash,
-                    offset: hashed_chunk.offset,
-                    data: hashed_chunk.data,
-                    cdata,
-                }
-            });
+            // The compressor is passed along with the chunk since it may not be Copy
+            pipe.input(
+                (hashed_chunk, compress_chunk.clone()),
+                |(hashed_chunk, compress_chunk): (HashedChunk, C)| {
+                    let (compression, cdata) = compress_chunk(&hashed_chunk.data);
+                    CompressedChunk {
+                        hash: hashed_chunk.hash,
+                        offset: hashed_chunk.offset,
+                        data: hashed_chunk.data,
+                        cdata,
+                        compression,
+                    }
+                },
+            );
         })?;
 
     Ok((file_size, file_hash, chunks))
@@ -1,30 +1,55 @@
 use atty::Stream;
 use blake2::{Blake2b, Digest};
+use crossbeam_channel::bounded;
+use crossbeam_utils::thread;
 use log::*;
+use std::cmp;
 use std::collections::HashSet;
+use std::env;
+use std::fs;
 use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::prelude::*;
 use std::io::BufWriter;
 use std::io::SeekFrom;
 use std::os::linux::fs::MetadataExt;
-use std::path::PathBuf;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
 use threadpool::ThreadPool;
 
 use crate::config;
 use crate::info_cmd;
 use bita::archive_reader::{ArchiveBackend, ArchiveReader};
+use bita::backend_registry::{file_path, BackendRegistry};
+use bita::chunk_cache;
+use bita::chunk_cache::ChunkCache;
 use bita::chunker::{Chunker, ChunkerParams};
 use bita::chunker_utils::*;
+use bita::clone_journal::CloneJournal;
 use bita::errors::*;
-use bita::remote_archive_backend::RemoteReader;
+use bita::in_place;
+use bita::in_place::{ChunkMove, MoveOp, MovePlan};
+use bita::mmap_archive_backend::MmapReader;
+use bita::para_pipe::ParaPipe;
+use bita::parallel_chunker::find_chunk_boundaries;
+use bita::signing;
 use bita::string_utils::*;
 
+// Amount of data to write to output between each sync of output and journal
+const JOURNAL_COMMIT_SIZE: u64 = 16 * 1024 * 1024;
+
+// Scan seed for chunks. If the seed chunk boundaries are given the seed is
+// split at these instead of scanning for boundaries.
 fn chunk_seed<T, F>(
     mut seed_input: T,
     chunker_params: &ChunkerParams,
+    seed_boundaries: Option<Vec<u64>>,
     hash_length: usize,
-    chunk_hash_set: &mut HashSet<HashBuf>,
+    chunk_hash_set: &Mutex<HashSet<HashBuf>>,
     mut chunk_callback: F,
     pool: &ThreadPool,
 ) -> Result<()>
@@ -34,7 +59,12 @@ where
 {
     // As the input file was not an archive we feed the data read so
     // far into the chunker.
-    let mut chunker = Chunker::new(chunker_params.clone(), &mut seed_input);
+    let mut chunker = match seed_boundaries {
+        Some(boundaries) => {
+            Chunker::with_boundaries(chunker_params.clone(), &mut seed_input, boundaries)
+        }
+        None => Chunker::new(chunker_params.clone(), &mut seed_input),
+    };
 
     // If input is an archive also check if chunker parameter
     // matches, otherwise error or warn user?
@@ -46,9 +76,8 @@ where
     };
     unique_chunks(&mut chunker, hasher, &pool, false, |hashed_chunk| {
         let hash = &hashed_chunk.hash[0..hash_length].to_vec();
-        if chunk_hash_set.contains(hash) {
+        if chunk_hash_set.lock().expect("lock").remove(hash) {
             chunk_callback(hash, &hashed_chunk.data);
-            chunk_hash_set.remove(hash);
         }
     })
     .chain_err(|| "failed to get unique chunks")?;
@@ -56,60 +85,399 @@ where
     Ok(())
 }
 
+// Read chunks from a seed which is known to be a clone of the given seed archive.
+// The chunks are read directly from their offset in seed and verified by hash,
+// without scanning the seed for chunk boundaries.
+fn seed_from_archive_dict<F>(
+    seed_file: &mut File,
+    seed_archive: &ArchiveReader,
+    hash_length: usize,
+    chunk_hash_set: &Mutex<HashSet<HashBuf>>,
+    chunk_callback: F,
+    pool: &ThreadPool,
+) -> Result<()>
+where
+    F: FnMut(&HashBuf, &[u8]),
+{
+    // If the seed archive uses a hash at least as long as ours we can skip
+    // reading the chunks which we know are not needed.
+    let can_filter = seed_archive.hash_length >= hash_length;
+    let mut candidates_seen: HashSet<&[u8]> = HashSet::new();
+    let candidates: Vec<(u64, usize)> = {
+        let chunks_left = chunk_hash_set.lock().expect("lock");
+        seed_archive
+            .source_layout()
+            .into_iter()
+            .filter(|(_offset, descriptor)| {
+                candidates_seen.insert(&descriptor.checksum[..])
+                    && (!can_filter || chunks_left.contains(&descriptor.checksum[..hash_length]))
+            })
+            .map(|(offset, descriptor)| (offset, descriptor.source_size as usize))
+            .collect()
+    };
+    read_seed_chunks(
+        seed_file,
+        candidates,
+        hash_length,
+        chunk_hash_set,
+        chunk_callback,
+        pool,
+    )
+}
+
+// Read chunks at the given (offset, size) of seed. Chunks which are not
+// (fully) inside of the seed are skipped, as the seed might be truncated or
+// modified since it was cloned. Every chunk read is verified by its hash.
+fn read_seed_chunks<T, F>(
+    seed: &mut T,
+    candidates: Vec<(u64, usize)>,
+    hash_length: usize,
+    chunk_hash_set: &Mutex<HashSet<HashBuf>>,
+    mut chunk_callback: F,
+    pool: &ThreadPool,
+) -> Result<()>
+where
+    T: Read + Seek,
+    F: FnMut(&HashBuf, &[u8]),
+{
+    let seed_size = seed
+        .seek(SeekFrom::End(0))
+        .chain_err(|| "failed to seek seed file")?;
+    let mut pipe = ParaPipe::new_output(pool, |(hash, chunk_data): (HashBuf, Vec<u8>)| {
+        let hash = &hash[0..hash_length].to_vec();
+        if chunk_hash_set.lock().expect("lock").remove(hash) {
+            chunk_callback(hash, &chunk_data);
+        }
+    });
+    let mut chunks_skipped = 0;
+    for (offset, size) in candidates {
+        if offset + size as u64 > seed_size {
+            chunks_skipped += 1;
+            continue;
+        }
+        let mut chunk_data = vec![0; size];
+        seed.seek(SeekFrom::Start(offset))
+            .chain_err(|| "failed to seek seed file")?;
+        match seed.read_exact(&mut chunk_data) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                // Seed was truncated while reading
+                chunks_skipped += 1;
+                continue;
+            }
+            Err(e) => return Err(e).chain_err(|| "failed to read chunk from seed file"),
+        }
+        pipe.input(chunk_data, |chunk_data: Vec<u8>| {
+            // Generate strong hash for the chunk
+            let mut hasher = Blake2b::new();
+            hasher.input(&chunk_data);
+            (hasher.result().to_vec(), chunk_data)
+        });
+    }
+    if chunks_skipped > 0 {
+        debug!(
+            "Skipped {} chunks beyond end of seed ({})",
+            chunks_skipped,
+            size_to_str(seed_size)
+        );
+    }
+
+    Ok(())
+}
+
+// Get the target of a chunk cache, or None if the file is not a readable cache.
+fn cache_target(path: &Path) -> Option<PathBuf> {
+    let mut file = File::open(path).ok()?;
+    if !seed_is_cache(&mut file).ok()? {
+        return None;
+    }
+    chunk_cache::read_target(&mut file).ok()
+}
+
+// Check if a seed file is a chunk cache. The file is rewinded after check.
+fn seed_is_cache(seed_file: &mut File) -> Result<bool> {
+    let mut magic = vec![];
+    seed_file
+        .take(chunk_cache::CACHE_MAGIC.len() as u64)
+        .read_to_end(&mut magic)
+        .chain_err(|| "failed to read seed file")?;
+    seed_file
+        .seek(SeekFrom::Start(0))
+        .chain_err(|| "failed to seek seed file")?;
+    Ok(chunk_cache::is_cache(&magic))
+}
+
+// Read chunks from a seed file. If the seed is a chunk cache, or a seed archive
+// is given, chunks are read using the dictionary. Otherwise the seed is scanned
+// for chunks.
+fn seed_from_file<F>(
+    seed_path: &Path,
+    seed_archive: Option<&ArchiveReader>,
+    chunker_params: &ChunkerParams,
+    parallel_chunking: bool,
+    hash_length: usize,
+    chunks_left: &Mutex<HashSet<HashBuf>>,
+    mut chunk_callback: F,
+    pool: &ThreadPool,
+) -> Result<()>
+where
+    F: FnMut(&HashBuf, &[u8]),
+{
+    let mut chunks_used = 0;
+    let seed_output = |checksum: &HashBuf, chunk_data: &[u8]| {
+        chunks_used += 1;
+        chunk_callback(checksum, chunk_data);
+    };
+    let mut seed_file = File::open(seed_path)
+        .chain_err(|| format!("failed to open seed file ({})", seed_path.display()))?;
+    if seed_is_cache(&mut seed_file)? {
+        let cache = ChunkCache::try_init(&mut seed_file)
+            .chain_err(|| format!("failed to read chunk cache ({})", seed_path.display()))?;
+        let mut target_file = File::open(&cache.target)
+            .chain_err(|| format!("failed to open cached file ({})", cache.target.display()))?;
+        info!(
+            "Reading chunks from {} using chunk cache {}...",
+            cache.target.display(),
+            seed_path.display()
+        );
+        seed_from_archive_dict(
+            &mut target_file,
+            &cache.archive,
+            hash_length,
+            chunks_left,
+            seed_output,
+            pool,
+        )
+        .chain_err(|| {
+            format!(
+                "failed to read chunks from cached file ({})",
+                cache.target.display()
+            )
+        })?;
+    } else if let Some(seed_archive) = seed_archive {
+        info!(
+            "Reading chunks from {} using seed archive dictionary...",
+            seed_path.display()
+        );
+        seed_from_archive_dict(
+            &mut seed_file,
+            seed_archive,
+            hash_length,
+            chunks_left,
+            seed_output,
+            pool,
+        )
+        .chain_err(|| {
+            format!(
+                "failed to read chunks from seed file ({})",
+                seed_path.display()
+            )
+        })?;
+    } else {
+        let seekable = seed_file.seek(SeekFrom::Current(0)).is_ok();
+        if parallel_chunking && !seekable {
+            warn!(
+                "Seed file {} is not seekable, falling back to serial chunking",
+                seed_path.display()
+            );
+        }
+        let seed_boundaries = if parallel_chunking && seekable {
+            info!(
+                "Chunking {} using {} threads...",
+                seed_path.display(),
+                pool.max_count()
+            );
+            Some(
+                find_chunk_boundaries(chunker_params, pool.max_count(), || File::open(seed_path))
+                    .chain_err(|| format!("failed to chunk seed file ({})", seed_path.display()))?,
+            )
+        } else {
+            None
+        };
+        info!("Scanning {} for chunks...", seed_path.display());
+        chunk_seed(
+            seed_file,
+            chunker_params,
+            seed_boundaries,
+            hash_length,
+            chunks_left,
+            seed_output,
+            pool,
+        )?;
+    }
+    info!(
+        "Used {} chunks from seed file {}",
+        chunks_used,
+        seed_path.display(),
+    );
+    Ok(())
+}
+
+// Read chunks from the seed files concurrently, using up to one thread per core.
+// Seeds are picked in the given order. Chunks found are forwarded to the output
+// from the calling thread.
+fn seed_from_files_parallel<F>(
+    seed_files: &[PathBuf],
+    seed_archive: Option<&ArchiveReader>,
+    chunker_params: &ChunkerParams,
+    parallel_chunking: bool,
+    hash_length: usize,
+    chunks_left: &Mutex<HashSet<HashBuf>>,
+    mut chunk_callback: F,
+    pool: &ThreadPool,
+) -> Result<()>
+where
+    F: FnMut(&Path, &HashBuf, &[u8]),
+{
+    let (chunk_tx, chunk_rx) = bounded::<(&Path, HashBuf, Vec<u8>)>(pool.max_count() * 4);
+    let next_seed = AtomicUsize::new(0);
+    let next_seed = &next_seed;
+    let num_threads = cmp::min(seed_files.len(), pool.max_count());
+    let results = thread::scope(|scope| {
+        let seed_threads: Vec<_> = (0..num_threads)
+            .map(|_| {
+                let chunk_tx = chunk_tx.clone();
+                let pool = pool.clone();
+                scope.spawn(move |_| -> Result<()> {
+                    while let Some(seed_path) =
+                        seed_files.get(next_seed.fetch_add(1, Ordering::SeqCst))
+                    {
+                        if chunks_left.lock().expect("lock").is_empty() {
+                            break;
+                        }
+                        seed_from_file(
+                            seed_path,
+                            seed_archive,
+                            chunker_params,
+                            parallel_chunking,
+                            hash_length,
+                            chunks_left,
+                            |checksum, chunk_data| {
+                                chunk_tx
+                                    .send((seed_path, checksum.clone(), chunk_data.to_vec()))
+                                    .expect("forward chunk");
+                            },
+                            &pool,
+                        )?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        drop(chunk_tx);
+
+        for (seed_path, checksum, chunk_data) in chunk_rx.iter() {
+            chunk_callback(seed_path, &checksum, &chunk_data);
+        }
+        seed_threads
+            .into_iter()
+            .map(|seed_thread| seed_thread.join().expect("seed thread"))
+            .collect::<Vec<Result<()>>>()
+    })
+    .expect("seed threads");
+    results.into_iter().collect()
+}
+
 fn clone_to_output<T, F>(
     pool: &ThreadPool,
     archive_backend: T,
     archive: &ArchiveReader,
     seed_files: &[PathBuf],
     seed_stdin: bool,
+    parallel_seeds: bool,
+    parallel_chunking: bool,
+    seed_archive: Option<&ArchiveReader>,
     chunker_params: ChunkerParams,
-    mut chunks_left: HashSet<HashBuf>,
+    chunks_left: HashSet<HashBuf>,
     mut chunk_output: F,
 ) -> Result<()>
 where
     T: ArchiveBackend,
-    F: FnMut(&str, &HashBuf, &[u8]),
+    F: FnMut(&str, &HashBuf, &[u8]) -> Result<()>,
 {
     let mut total_read_from_seed = 0;
+    let chunks_left = Mutex::new(chunks_left);
+
+    // The seed callbacks can not fail, so the first output error is kept and
+    // returned when done with the seed. Chunks found after an error are dropped.
+    let mut output_error: Option<Error> = None;
+    let mut seed_output = |output_error: &mut Option<Error>,
+                           chunk_source: &str,
+                           checksum: &HashBuf,
+                           chunk_data: &[u8]| {
+        if output_error.is_none() {
+            total_read_from_seed += chunk_data.len();
+            if let Err(err) = chunk_output(chunk_source, checksum, chunk_data) {
+                *output_error = Some(err);
+            }
+        }
+    };
 
     // Run input seed files through chunker and use chunks which are in the target file.
     // Start with scanning stdin, if not a tty.
     if seed_stdin && !atty::is(Stream::Stdin) {
         let stdin = io::stdin();
-        let chunks_missing = chunks_left.len();
+        let mut chunks_used = 0;
         let stdin = stdin.lock();
+        if parallel_chunking {
+            info!("Stdin can not be split into regions, chunking it serially");
+        }
         info!("Scanning stdin for chunks...");
         chunk_seed(
             stdin,
             &chunker_params,
+            None,
             archive.hash_length,
-            &mut chunks_left,
+            &chunks_left,
             |checksum, chunk_data| {
-                total_read_from_seed += chunk_data.len();
-                chunk_output("seed (stdin)", checksum, chunk_data);
+                chunks_used += 1;
+                seed_output(&mut output_error, "seed (stdin)", checksum, chunk_data);
             },
             &pool,
         )?;
-        info!(
-            "Used {} chunks from stdin",
-            chunks_missing - chunks_left.len()
-        );
+        if let Some(err) = output_error.take() {
+            return Err(err);
+        }
+        info!("Used {} chunks from stdin", chunks_used);
     }
     // Now scan through all given seed files
-    for seed_path in seed_files {
-        if !chunks_left.is_empty() {
-            let chunks_missing = chunks_left.len();
-            let seed_file = File::open(&seed_path)
-                .chain_err(|| format!("failed to open seed file ({})", seed_path.display()))?;
-            info!("Scanning {} for chunks...", seed_path.display());
-            chunk_seed(
-                seed_file,
+    if parallel_seeds && seed_files.len() > 1 {
+        seed_from_files_parallel(
+            seed_files,
+            seed_archive,
+            &chunker_params,
+            parallel_chunking,
+            archive.hash_length,
+            &chunks_left,
+            |seed_path, checksum, chunk_data| {
+                seed_output(
+                    &mut output_error,
+                    &format!("seed ({})", seed_path.display()),
+                    checksum,
+                    chunk_data,
+                );
+            },
+            &pool,
+        )?;
+        if let Some(err) = output_error.take() {
+            return Err(err);
+        }
+    } else {
+        for seed_path in seed_files {
+            if chunks_left.lock().expect("lock").is_empty() {
+                info!("All chunks found, skipping remaining seeds");
+                break;
+            }
+            seed_from_file(
+                seed_path,
+                seed_archive,
                 &chunker_params,
+                parallel_chunking,
                 archive.hash_length,
-                &mut chunks_left,
+                &chunks_left,
                 |checksum, chunk_data| {
-                    total_read_from_seed += chunk_data.len();
-                    chunk_output(
+                    seed_output(
+                        &mut output_error,
                         &format!("seed ({})", seed_path.display()),
                         checksum,
                         chunk_data,
@@ -117,23 +485,19 @@ where
                 },
                 &pool,
             )?;
-            info!(
-                "Used {} chunks from seed file {}",
-                chunks_missing - chunks_left.len(),
-                seed_path.display(),
-            );
+            if let Some(err) = output_error.take() {
+                return Err(err);
+            }
         }
     }
+    let chunks_left = chunks_left.into_inner().expect("lock");
 
     // Fetch rest of the chunks from archive
     let total_from_archive = archive.read_chunk_data(
         &pool,
         archive_backend,
         &chunks_left,
-        |checksum, chunk_data| {
-            chunk_output("archive", &checksum, chunk_data);
-            Ok(())
-        },
+        |checksum, chunk_data| chunk_output("archive", &checksum, chunk_data),
     )?;
 
     info!(
@@ -145,11 +509,248 @@ where
     Ok(())
 }
 
-fn prepare_unpack_output(output_file: &mut File, source_file_size: u64) -> Result<()> {
-    let meta = output_file
+// Add all regular files in the directory, and its sub directories, to files.
+fn find_files_in_dir(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let entries =
+        fs::read_dir(dir).chain_err(|| format!("failed to read seed dir ({})", dir.display()))?;
+    for entry in entries {
+        let entry = entry.chain_err(|| format!("failed to read seed dir ({})", dir.display()))?;
+        let path = entry.path();
+        let file_type = entry
+            .file_type()
+            .chain_err(|| format!("failed to get file type ({})", path.display()))?;
+        // Symlinks to files are followed but not symlinks to directories
+        if file_type.is_dir() {
+            find_files_in_dir(&path, files)?;
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Get the seed files to use while cloning. Files given explicitly are used
+// first, in the given order. Then follows the files found in seed directories
+// or by glob patterns, the most recently modified (and then the largest) file
+// first as it is the most likely to hold chunks of the archive.
+fn find_seed_files(config: &config::CloneConfig) -> Result<Vec<PathBuf>> {
+    let mut found_files = Vec::new();
+    for dir in &config.seed_dirs {
+        find_files_in_dir(dir, &mut found_files)?;
+    }
+    for pattern in &config.seed_patterns {
+        let paths =
+            glob::glob(pattern).chain_err(|| format!("invalid seed pattern ({})", pattern))?;
+        let files_before = found_files.len();
+        for path in paths {
+            let path = path.chain_err(|| format!("failed to match seed pattern ({})", pattern))?;
+            if path.is_file() {
+                found_files.push(path);
+            }
+        }
+        if found_files.len() == files_before {
+            warn!("No seed files matching {}", pattern);
+        }
+    }
+
+    let mut found_files = found_files
+        .into_iter()
+        .map(|path| {
+            let metadata = fs::metadata(&path)
+                .chain_err(|| format!("failed to get metadata ({})", path.display()))?;
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            Ok((modified, metadata.len(), path))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    found_files.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)));
+
+    // Skip duplicates, and the output file if found as it is written while
+    // cloning. The same goes for a chunk cache which describes the output.
+    let output = fs::canonicalize(&config.output).ok();
+    let mut seen = HashSet::new();
+    let mut seed_files = Vec::new();
+    for path in &config.seed_files {
+        if seen.insert(fs::canonicalize(path).unwrap_or_else(|_| path.clone())) {
+            seed_files.push(path.clone());
+        }
+    }
+    for (_modified, _size, path) in found_files {
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if Some(&canonical) == output.as_ref() {
+            continue;
+        }
+        if output.is_some() {
+            if let Some(target) = cache_target(&path) {
+                if fs::canonicalize(&target).ok() == output {
+                    debug!("Skipping chunk cache {} of output", path.display());
+                    continue;
+                }
+            }
+        }
+        if seen.insert(canonical) {
+            seed_files.push(path);
+        }
+    }
+    Ok(seed_files)
+}
+
+// Scan the output file for chunks present in archive and get the moves needed
+// to place them where they belong in the target.
+fn scan_output_chunks(
+    output_file: &mut File,
+    archive: &ArchiveReader,
+    chunks_left: &mut HashSet<HashBuf>,
+    pool: &ThreadPool,
+) -> Result<Vec<ChunkMove>> {
+    output_file
+        .seek(SeekFrom::Start(0))
+        .chain_err(|| "unable to seek output file")?;
+    let mut chunker = Chunker::new(archive.chunker_params.clone(), output_file);
+    let hasher = |data: &[u8]| {
+        let mut hasher = Blake2b::new();
+        hasher.input(data);
+        hasher.result().to_vec()
+    };
+    let hash_length = archive.hash_length;
+    let mut moves = Vec::new();
+    unique_chunks(&mut chunker, hasher, &pool, false, |hashed_chunk| {
+        let hash = hashed_chunk.hash[0..hash_length].to_vec();
+        if chunks_left.remove(&hash) {
+            moves.push(ChunkMove {
+                source: hashed_chunk.offset,
+                size: hashed_chunk.data.len() as u64,
+                targets: archive.chunk_source_offsets(&hash),
+            });
+        }
+    })
+    .chain_err(|| "failed to scan output file")?;
+    Ok(moves)
+}
+
+// Create a uniquely named spill file in the given directory. The file is
+// removed directly and kept until closed. All of the spill space is written
+// up front, so that running out of space fails before any chunk is moved.
+fn create_spill_file(spill_dir: &Path, spill_size: u64) -> Result<File> {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let mut attempt = 0;
+    let (spill_path, mut spill_file) = loop {
+        let spill_path = spill_dir.join(format!(
+            ".bita-spill-{}-{}-{}",
+            process::id(),
+            nanos,
+            attempt
+        ));
+        match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&spill_path)
+        {
+            Ok(spill_file) => break (spill_path, spill_file),
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => {
+                attempt += 1;
+            }
+            Err(err) => {
+                return Err(err).chain_err(|| {
+                    format!("failed to create spill file ({})", spill_path.display())
+                })
+            }
+        }
+    };
+    fs::remove_file(&spill_path).chain_err(|| "failed to remove spill file")?;
+
+    io::copy(&mut io::repeat(0).take(spill_size), &mut spill_file).chain_err(|| {
+        format!(
+            "failed to reserve {} for spill file in {}",
+            size_to_str(spill_size),
+            spill_dir.display()
+        )
+    })?;
+    spill_file
+        .sync_all()
+        .chain_err(|| "failed to sync spill file")?;
+    Ok(spill_file)
+}
+
+// Execute a plan for moving chunks inside of the output file. Chunks which can
+// not be moved directly are spilled to a file in spill_dir.
+// Returns the number of bytes written to output.
+fn move_chunks(output_file: &mut File, plan: &MovePlan, spill_dir: &Path) -> Result<u64> {
+    let mut spill_file = if plan.spill_size > 0 {
+        Some(create_spill_file(spill_dir, plan.spill_size)?)
+    } else {
+        None
+    };
+    let mut buf: Vec<u8> = Vec::new();
+    let mut total_written: u64 = 0;
+    for op in &plan.ops {
+        match op {
+            MoveOp::Copy {
+                source,
+                size,
+                targets,
+            } => {
+                buf.resize(*size as usize, 0);
+                output_file
+                    .read_exact_at(&mut buf, *source)
+                    .chain_err(|| "failed to read output file")?;
+                for target in targets {
+                    output_file
+                        .write_all_at(&buf, *target)
+                        .chain_err(|| "failed to write output file")?;
+                    total_written += size;
+                }
+            }
+            MoveOp::Spill {
+                source,
+                size,
+                spill_offset,
+            } => {
+                buf.resize(*size as usize, 0);
+                output_file
+                    .read_exact_at(&mut buf, *source)
+                    .chain_err(|| "failed to read output file")?;
+                spill_file
+                    .as_ref()
+                    .expect("spill file")
+                    .write_all_at(&buf, *spill_offset)
+                    .chain_err(|| "failed to write spill file")?;
+            }
+            MoveOp::Restore {
+                spill_offset,
+                size,
+                targets,
+            } => {
+                buf.resize(*size as usize, 0);
+                spill_file
+                    .as_mut()
+                    .expect("spill file")
+                    .read_exact_at(&mut buf, *spill_offset)
+                    .chain_err(|| "failed to read spill file")?;
+                for target in targets {
+                    output_file
+                        .write_all_at(&buf, *target)
+                        .chain_err(|| "failed to write output file")?;
+                    total_written += size;
+                }
+            }
+        }
+    }
+    Ok(total_written)
+}
+
+fn is_block_device(file: &File) -> Result<bool> {
+    let meta = file
         .metadata()
         .chain_err(|| "unable to get file meta data")?;
-    if meta.st_mode() & 0x6000 == 0x6000 {
+    Ok(meta.st_mode() & 0x6000 == 0x6000)
+}
+
+fn prepare_unpack_output(output_file: &mut File, source_file_size: u64) -> Result<()> {
+    if is_block_device(output_file)? {
         // Output is a block device
         let size = output_file
             .seek(SeekFrom::End(0))
@@ -176,13 +777,23 @@ fn prepare_unpack_output(output_file: &mut File, source_file_size: u64) -> Resul
 fn clone_archive<T>(
     mut archive_backend: T,
     config: &config::CloneConfig,
+    registry: &BackendRegistry,
     pool: &ThreadPool,
 ) -> Result<()>
 where
     T: ArchiveBackend,
 {
-    let archive = ArchiveReader::try_init(&mut archive_backend, &mut Vec::new())?;
-    let chunks_left = archive.chunk_hash_set();
+    let mut archive_header = Vec::new();
+    let archive = match config.header {
+        Some(ref header_input) => {
+            // Header is detached, archive backend only holds the chunk data
+            let mut header_backend = registry.open(header_input)?;
+            ArchiveReader::try_init(&mut header_backend, &mut archive_header)
+                .chain_err(|| format!("unable to read archive header ({})", header_input))?
+        }
+        None => ArchiveReader::try_init(&mut archive_backend, &mut archive_header)?,
+    };
+    let mut chunks_left = archive.chunk_hash_set();
 
     info_cmd::print_archive(&archive);
     println!();
@@ -195,6 +806,12 @@ where
             info!("Header checksum verified OK");
         }
     }
+    // Verify the header signature if requested
+    if let Some(ref key_path) = config.verify_key {
+        let key = signing::load_verify_key(key_path)?;
+        archive.verify_signature(&key)?;
+        info!("Header signature verified OK");
+    }
     info!(
         "Cloning archive {} to {}...",
         config.input,
@@ -204,14 +821,87 @@ where
     // Setup chunker to use when chunking seed input
     let chunker_params = archive.chunker_params.clone();
 
-    // Create or open output file.
+    let seed_files = find_seed_files(config)?;
+    if !config.seed_dirs.is_empty() || !config.seed_patterns.is_empty() {
+        info!("Using {} seed files", seed_files.len());
+    }
+
+    // Open the archive which describes the layout of the seed files
+    let seed_archive = match config.seed_archive_dict {
+        Some(ref seed_archive_input) => {
+            let seed_archive = open_archive(seed_archive_input, registry)
+                .chain_err(|| format!("failed to open seed archive {}", seed_archive_input))?;
+            if seed_archive.chunker_params != chunker_params {
+                warn!(
+                    "Seed archive {} uses different chunker parameters, chunks are unlikely to be found in seed",
+                    seed_archive_input
+                );
+            }
+            Some(seed_archive)
+        }
+        None => None,
+    };
+
+    // Open the journal and skip chunks already written by a previous clone
+    let mut journal = match config.journal {
+        Some(ref journal_path) if config.resume => {
+            let (journal, completed) =
+                CloneJournal::resume(journal_path, &archive.header_checksum, archive.hash_length)?;
+            if !completed.is_empty() {
+                info!(
+                    "Resuming clone, {} of {} chunks already written",
+                    completed.len(),
+                    chunks_left.len()
+                );
+            }
+            chunks_left.retain(|hash| !completed.contains(hash));
+            Some(journal)
+        }
+        Some(ref journal_path) => Some(CloneJournal::create(
+            journal_path,
+            &archive.header_checksum,
+            archive.hash_length,
+        )?),
+        None => None,
+    };
+
+    // Create or open output file. When cloning in place or resuming a clone
+    // the output may already exist.
     let mut output_file = OpenOptions::new()
         .write(true)
-        .create(config.force_create)
-        .create_new(!config.force_create)
+        .read(config.skip_unchanged || config.in_place)
+        .create(config.force_create || config.resume)
+        .create_new(!config.force_create && !config.in_place && !config.resume)
         .open(&config.output)
         .chain_err(|| "failed to open output file")?;
 
+    if config.in_place {
+        // A block device can not be resized, check its size before any data
+        // is moved.
+        if is_block_device(&output_file)? {
+            prepare_unpack_output(&mut output_file, archive.source_total_size)?;
+        }
+        info!("Scanning output {} for chunks...", config.output.display());
+        let moves = scan_output_chunks(&mut output_file, &archive, &mut chunks_left, pool)?;
+        info!("Found {} chunks in output", moves.len());
+        let plan = in_place::plan_moves(moves);
+        // Spill next to the output by default, unless output is a block device
+        let spill_dir = match config.spill_dir {
+            Some(ref spill_dir) => spill_dir.clone(),
+            None if is_block_device(&output_file)? => env::temp_dir(),
+            None => match config.output.parent() {
+                Some(dir) if dir != Path::new("") => dir.to_path_buf(),
+                _ => PathBuf::from("."),
+            },
+        };
+        let total_moved = move_chunks(&mut output_file, &plan, &spill_dir)?;
+        info!(
+            "Moved {} within output (spilled {}).",
+            size_to_str(total_moved),
+            size_to_str(plan.spill_size)
+        );
+    }
+
     // Clone and unpack archive
 
     // Check if the given output file is a regular file or block device.
@@ -220,12 +910,19 @@ where
     prepare_unpack_output(&mut output_file, archive.source_total_size)?;
 
     let mut output_file = BufWriter::new(output_file);
+    let mut total_written: u64 = 0;
+    let mut total_skipped: u64 = 0;
+    let mut current_data: Vec<u8> = Vec::new();
+    let mut uncommitted_size: u64 = 0;
     clone_to_output(
         pool,
         archive_backend,
         &archive,
-        &config.seed_files,
+        &seed_files,
         config.seed_stdin,
+        config.parallel_seeds,
+        config.parallel_chunking,
+        seed_archive.as_ref(),
         chunker_params,
         chunks_left,
         |chunk_source: &str, hash: &HashBuf, chunk_data: &[u8]| {
@@ -237,26 +934,198 @@ where
             );
 
             for offset in &archive.chunk_source_offsets(hash) {
-                output_file
-                    .seek(SeekFrom::Start(*offset))
-                    .expect("seek output");
-                output_file.write_all(chunk_data).expect("write output");
+                if write_chunk(
+                    &mut output_file,
+                    *offset,
+                    chunk_data,
+                    config.skip_unchanged,
+                    &mut current_data,
+                )? {
+                    total_written += chunk_data.len() as u64;
+                } else {
+                    total_skipped += chunk_data.len() as u64;
+                }
             }
+
+            if let Some(ref mut journal) = journal {
+                // Chunks are recorded in journal only when the output has been
+                // synced, to make sure they are on disk if the clone is interrupted.
+                journal.add(hash);
+                uncommitted_size += chunk_data.len() as u64;
+                if uncommitted_size >= JOURNAL_COMMIT_SIZE {
+                    output_file
+                        .flush()
+                        .chain_err(|| "failed to write output file")?;
+                    output_file
+                        .get_ref()
+                        .sync_data()
+                        .chain_err(|| "failed to sync output file")?;
+                    journal.commit()?;
+                    uncommitted_size = 0;
+                }
+            }
+            Ok(())
         },
     )?;
+    output_file
+        .flush()
+        .chain_err(|| "failed to write output file")?;
+
+    if let Some(ref journal_path) = config.journal {
+        // Clone is complete and the journal is no longer needed
+        output_file
+            .get_ref()
+            .sync_data()
+            .chain_err(|| "failed to sync output file")?;
+        drop(journal);
+        fs::remove_file(journal_path)
+            .chain_err(|| format!("failed to remove journal ({})", journal_path.display()))?;
+    }
+
+    if config.skip_unchanged {
+        info!(
+            "Wrote {} to output, skipped {} already in place.",
+            size_to_str(total_written),
+            size_to_str(total_skipped)
+        );
+    }
+
+    if let Some(ref cache_path) = config.chunk_cache {
+        // Store a cache which describes where chunks are placed in output
+        let target =
+            fs::canonicalize(&config.output).chain_err(|| "unable to resolve output file path")?;
+        let cache = chunk_cache::build_cache(&archive_header, &target)?;
+        fs::write(cache_path, cache)
+            .chain_err(|| format!("failed to write chunk cache ({})", cache_path.display()))?;
+        info!("Wrote chunk cache to {}", cache_path.display());
+    }
 
     Ok(())
 }
 
+// Write chunk at offset in output. If skip_unchanged is set the data already
+// in output is compared first and the chunk is only written if it differs.
+// Returns true if the chunk was written.
+fn write_chunk<T>(
+    output: &mut BufWriter<T>,
+    offset: u64,
+    chunk_data: &[u8],
+    skip_unchanged: bool,
+    current_data: &mut Vec<u8>,
+) -> Result<bool>
+where
+    T: Read + Write + Seek,
+{
+    output
+        .seek(SeekFrom::Start(offset))
+        .chain_err(|| "failed to seek output file")?;
+    if skip_unchanged {
+        // Seek on the writer flushes any buffered data so it's safe to read
+        // from the file here.
+        current_data.resize(chunk_data.len(), 0);
+        output
+            .get_mut()
+            .read_exact(current_data)
+            .chain_err(|| "failed to read output file")?;
+        if current_data[..] == chunk_data[..] {
+            return Ok(false);
+        }
+        output
+            .seek(SeekFrom::Start(offset))
+            .chain_err(|| "failed to seek output file")?;
+    }
+    output
+        .write_all(chunk_data)
+        .chain_err(|| "failed to write output file")?;
+    Ok(true)
+}
+
+// Get a backend registry where remote backends are setup using the config
+fn backend_registry(config: &config::CloneConfig) -> BackendRegistry {
+    let mut registry = BackendRegistry::with_http(config.http.clone());
+    if config.mmap {
+        registry.register(
+            "file",
+            |location: &str| -> Result<Box<dyn ArchiveBackend>> {
+                Ok(Box::new(MmapReader::open(Path::new(file_path(location)))?))
+            },
+        );
+    }
+    registry
+}
+
+fn open_archive(input: &str, registry: &BackendRegistry) -> Result<ArchiveReader> {
+    let mut archive_backend = registry.open(input)?;
+    ArchiveReader::try_init(&mut archive_backend, &mut Vec::new())
+}
+
 pub fn run(config: &config::CloneConfig, pool: &ThreadPool) -> Result<()> {
-    if &config.input[0..7] == "http://" || &config.input[0..8] == "https://" {
-        let remote_source = RemoteReader::new(&config.input);
-        clone_archive(remote_source, config, pool)?;
-    } else {
-        let local_file =
-            File::open(&config.input).chain_err(|| format!("unable to open {}", config.input))?;
-        clone_archive(local_file, config, pool)?;
+    let registry = backend_registry(config);
+    let archive_backend = registry.open(&config.input)?;
+    clone_archive(archive_backend, config, &registry, pool)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn chunk_hash(data: &[u8]) -> HashBuf {
+        let mut hasher = Blake2b::new();
+        hasher.input(data);
+        hasher.result().to_vec()
     }
 
-    Ok(())
+    #[test]
+    fn read_chunks_from_truncated_seed() {
+        let source: Vec<u8> = (0..4000).map(|v| (v * 7 % 251) as u8).collect();
+        let candidates: Vec<(u64, usize)> = (0..4).map(|i| (i * 1000, 1000)).collect();
+        let chunk_hash_set: Mutex<HashSet<HashBuf>> = Mutex::new(
+            candidates
+                .iter()
+                .map(|(offset, size)| {
+                    chunk_hash(&source[*offset as usize..*offset as usize + size])
+                })
+                .collect(),
+        );
+        // Seed ends in the middle of the third chunk
+        let mut seed = Cursor::new(source[..2500].to_vec());
+        let mut chunks_read = Vec::new();
+        read_seed_chunks(
+            &mut seed,
+            candidates,
+            64,
+            &chunk_hash_set,
+            |_hash, data| chunks_read.push(data.to_vec()),
+            &ThreadPool::new(2),
+        )
+        .unwrap();
+        chunks_read.sort();
+        let mut expected = vec![source[0..1000].to_vec(), source[1000..2000].to_vec()];
+        expected.sort();
+        assert_eq!(chunks_read, expected);
+        assert_eq!(chunk_hash_set.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn skip_unchanged_chunks() {
+        let mut output = BufWriter::new(Cursor::new(b"AAAABBBBCCCC".to_vec()));
+        let mut current_data = Vec::new();
+        // Chunk already in place is not written
+        assert!(!write_chunk(&mut output, 0, b"AAAA", true, &mut current_data).unwrap());
+        // Changed chunk is written
+        assert!(write_chunk(&mut output, 4, b"XXXX", true, &mut current_data).unwrap());
+        assert!(!write_chunk(&mut output, 8, b"CCCC", true, &mut current_data).unwrap());
+        // Without skip all chunks are written
+        assert!(write_chunk(&mut output, 8, b"CCCC", false, &mut current_data).unwrap());
+        output.flush().unwrap();
+        assert_eq!(&output.get_ref().get_ref()[..], b"AAAAXXXXCCCC");
+    }
+
+    #[test]
+    fn skip_unchanged_read_error() {
+        // Reading past end of output is an error and not a panic
+        let mut output = BufWriter::new(Cursor::new(b"AAAA".to_vec()));
+        assert!(write_chunk(&mut output, 2, b"AAAA", true, &mut Vec::new()).is_err());
+    }
 }
@@ -0,0 +1,212 @@
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::chunker_utils::HashBuf;
+use crate::errors::*;
+
+// A clone journal keeps track of which chunks have been written to the
+// output of a clone, so that an interrupted clone can be resumed.
+//
+// The journal file consists of the journal magic and the header checksum of
+// the archive being cloned, followed by the (truncated) hash of every chunk
+// which has been written to output.
+pub const JOURNAL_MAGIC: &[u8; 6] = b"BITAJ\0";
+
+const HEADER_CHECKSUM_SIZE: usize = 64;
+
+pub struct CloneJournal {
+    file: File,
+    hash_length: usize,
+    pending: Vec<HashBuf>,
+}
+
+impl CloneJournal {
+    // Create a new journal, any existing journal at path is replaced.
+    pub fn create(path: &Path, header_checksum: &[u8], hash_length: usize) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .chain_err(|| format!("failed to create journal ({})", path.display()))?;
+        file.write_all(JOURNAL_MAGIC)
+            .and_then(|_| file.write_all(header_checksum))
+            .and_then(|_| file.sync_data())
+            .chain_err(|| "failed to write journal")?;
+        Ok(CloneJournal {
+            file,
+            hash_length,
+            pending: Vec::new(),
+        })
+    }
+
+    // Open an existing journal to resume a clone. Returns the journal together
+    // with the hashes of all chunks which have already been written.
+    // If there is no journal at path a new one is created.
+    pub fn resume(
+        path: &Path,
+        header_checksum: &[u8],
+        hash_length: usize,
+    ) -> Result<(Self, HashSet<HashBuf>)> {
+        if !path.exists() {
+            return Ok((
+                Self::create(path, header_checksum, hash_length)?,
+                HashSet::new(),
+            ));
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .chain_err(|| format!("failed to open journal ({})", path.display()))?;
+        let mut journal = Vec::new();
+        file.read_to_end(&mut journal)
+            .chain_err(|| "failed to read journal")?;
+
+        let header_size = JOURNAL_MAGIC.len() + HEADER_CHECKSUM_SIZE;
+        if journal.len() < header_size || &journal[0..JOURNAL_MAGIC.len()] != JOURNAL_MAGIC {
+            bail!("invalid journal ({})", path.display());
+        }
+        if &journal[JOURNAL_MAGIC.len()..header_size] != header_checksum {
+            bail!(
+                "journal ({}) does not belong to the archive being cloned",
+                path.display()
+            );
+        }
+
+        // An interrupted write may have left a partial hash at the end
+        let entries = &journal[header_size..];
+        let complete_size = entries.len() - entries.len() % hash_length;
+        let completed: HashSet<HashBuf> = entries[..complete_size]
+            .chunks(hash_length)
+            .map(|hash| hash.to_vec())
+            .collect();
+        file.set_len((header_size + complete_size) as u64)
+            .chain_err(|| "failed to truncate journal")?;
+        file.seek(std::io::SeekFrom::End(0))
+            .chain_err(|| "failed to seek journal")?;
+
+        Ok((
+            CloneJournal {
+                file,
+                hash_length,
+                pending: Vec::new(),
+            },
+            completed,
+        ))
+    }
+
+    // Add a written chunk to the journal. The chunk is not recorded until
+    // commit is called.
+    pub fn add(&mut self, hash: &[u8]) {
+        self.pending.push(hash[0..self.hash_length].to_vec());
+    }
+
+    // Record all added chunks. The output data of the chunks must have been
+    // synced to disk before calling commit.
+    pub fn commit(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let entries: Vec<u8> = self.pending.iter().flatten().cloned().collect();
+        self.file
+            .write_all(&entries)
+            .and_then(|_| self.file.sync_data())
+            .chain_err(|| "failed to write journal")?;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    fn journal_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("bita-journal-{}-{}", process::id(), name))
+    }
+
+    #[test]
+    fn resume_committed_chunks() {
+        let path = journal_path("round-trip");
+        let checksum = vec![1; HEADER_CHECKSUM_SIZE];
+        let mut journal = CloneJournal::create(&path, &checksum, 4).unwrap();
+        journal.add(&[1, 2, 3, 4, 5]);
+        journal.add(&[6, 7, 8, 9, 10]);
+        journal.commit().unwrap();
+        // Not committed and not recorded
+        journal.add(&[11, 12, 13, 14, 15]);
+        drop(journal);
+
+        let (mut journal, completed) = CloneJournal::resume(&path, &checksum, 4).unwrap();
+        let expected: HashSet<HashBuf> = vec![vec![1, 2, 3, 4], vec![6, 7, 8, 9]]
+            .into_iter()
+            .collect();
+        assert_eq!(completed, expected);
+
+        // Chunks committed after resume are appended
+        journal.add(&[11, 12, 13, 14]);
+        journal.commit().unwrap();
+        drop(journal);
+        let (_journal, completed) = CloneJournal::resume(&path, &checksum, 4).unwrap();
+        assert_eq!(completed.len(), 3);
+        assert!(completed.contains(&vec![11, 12, 13, 14]));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn resume_after_partial_entry() {
+        let path = journal_path("partial");
+        let checksum = vec![2; HEADER_CHECKSUM_SIZE];
+        let mut journal = CloneJournal::create(&path, &checksum, 4).unwrap();
+        journal.add(&[1, 2, 3, 4]);
+        journal.commit().unwrap();
+        drop(journal);
+        // Simulate an interrupted write of the last entry
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[5, 6]).unwrap();
+        drop(file);
+
+        let (mut journal, completed) = CloneJournal::resume(&path, &checksum, 4).unwrap();
+        assert_eq!(completed.len(), 1);
+        assert!(completed.contains(&vec![1, 2, 3, 4]));
+        journal.add(&[5, 6, 7, 8]);
+        journal.commit().unwrap();
+        drop(journal);
+        // The partial entry is dropped and does not corrupt later entries
+        let (_journal, completed) = CloneJournal::resume(&path, &checksum, 4).unwrap();
+        assert_eq!(completed.len(), 2);
+        assert!(completed.contains(&vec![5, 6, 7, 8]));
+        assert_eq!(
+            fs::metadata(&path).unwrap().len() as usize,
+            JOURNAL_MAGIC.len() + HEADER_CHECKSUM_SIZE + 8
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reject_journal_of_other_archive() {
+        let path = journal_path("other-archive");
+        let journal = CloneJournal::create(&path, &[3; HEADER_CHECKSUM_SIZE], 4).unwrap();
+        drop(journal);
+        assert!(CloneJournal::resume(&path, &[4; HEADER_CHECKSUM_SIZE], 4).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn resume_without_journal() {
+        let path = journal_path("missing");
+        let _ = fs::remove_file(&path);
+        let (_journal, completed) =
+            CloneJournal::resume(&path, &[5; HEADER_CHECKSUM_SIZE], 4).unwrap();
+        assert!(completed.is_empty());
+        assert!(path.exists());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -7,15 +7,22 @@ use std::fs;
 use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
 use threadpool::ThreadPool;
 
 use crate::config::CompressConfig;
 use crate::info_cmd;
 use bita::archive;
 use bita::chunk_dictionary;
-use bita::chunker::{Chunker, ChunkerParams};
+use bita::chunk_dictionary::{ChunkDictionary_SignatureType, ChunkerParameters_ChunkingAlgorithm};
+use bita::chunker::{Chunker, ChunkerParams, ChunkingAlgorithm};
 use bita::chunker_utils::*;
+use bita::compression;
+use bita::compression::{Compression, ZstdDictionary};
 use bita::errors::*;
+use bita::parallel_chunker::find_chunk_boundaries;
+use bita::signing;
 
 pub const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -26,24 +33,75 @@ struct ChunkFileDescriptor {
     chunk_order: Vec<ChunkSourceDescriptor>,
 }
 
+fn chunker_params(config: &CompressConfig) -> ChunkerParams {
+    match config.chunking_algorithm {
+        ChunkingAlgorithm::BuzHash => ChunkerParams::new(
+            config.chunk_filter_bits,
+            config.min_chunk_size,
+            config.max_chunk_size,
+            config.hash_window_size,
+            archive::BUZHASH_SEED,
+        ),
+        ChunkingAlgorithm::FixedSize => ChunkerParams::new_fixed_size(config.max_chunk_size),
+        ChunkingAlgorithm::FastCdc => ChunkerParams::new_fastcdc(
+            config.chunk_filter_bits,
+            config.min_chunk_size,
+            config.max_chunk_size,
+        ),
+    }
+}
+
+// Train a zstd dictionary on a sample of the chunks in the input file
+fn train_zstd_dictionary(
+    config: &CompressConfig,
+    input_path: &Path,
+    dictionary_size: usize,
+) -> Result<Vec<u8>> {
+    let mut src_file = File::open(input_path)
+        .chain_err(|| format!("unable to open input file ({})", input_path.display()))?;
+    let file_size = src_file
+        .metadata()
+        .chain_err(|| "unable to get input file size")?
+        .len();
+
+    // Sample about a hundred times the dictionary size of data, spread evenly
+    // over the input file.
+    let sample_step = std::cmp::max(1, file_size / (100 * dictionary_size as u64)) as usize;
+    let mut samples = Vec::new();
+    let mut chunker = Chunker::new(chunker_params(config), &mut src_file);
+    let mut chunk_index = 0;
+    while let Some((_offset, data)) = chunker.scan().chain_err(|| "unable to read input file")? {
+        if chunk_index % sample_step == 0 {
+            samples.push(data.to_vec());
+        }
+        chunk_index += 1;
+    }
+
+    let dictionary = compression::train_zstd_dictionary(&samples, dictionary_size)?;
+    info!(
+        "Trained zstd dictionary of {} from {} chunks",
+        size_to_str(dictionary.len()),
+        samples.len()
+    );
+    Ok(dictionary)
+}
+
 fn chunk_into_file(
     config: &CompressConfig,
     pool: &ThreadPool,
     chunk_file: &mut File,
+    zstd_dictionary: Option<Arc<ZstdDictionary>>,
 ) -> Result<ChunkFileDescriptor> {
     // Setup the chunker
-    let chunker_params = ChunkerParams::new(
-        config.chunk_filter_bits,
-        config.min_chunk_size,
-        config.max_chunk_size,
-        config.hash_window_size,
-        archive::BUZHASH_SEED,
-    );
+    let chunker_params = chunker_params(config);
 
-    // Compress a chunk
-    let compression = config.compression;
-    let chunk_compressor =
-        move |data: &[u8]| -> Vec<u8> { compression.compress(data).expect("compress data") };
+    // Compress a chunk, using the compression which gives the smallest result
+    let compressions = Arc::new(config.compressions.clone());
+    let chunk_compressor = move |data: &[u8]| -> (Compression, Vec<u8>) {
+        Compression::compress_smallest(&compressions, data, zstd_dictionary.as_deref())
+            .expect("compress data")
+    };
+    let archive_compression = config.compressions[0];
 
     // Generate strong hash for a chunk
     fn hasher(data: &[u8]) -> Vec<u8> {
@@ -65,19 +123,21 @@ fn chunk_into_file(
             // For each unique and compressed chunk
             let hash = &comp_chunk.hash[0..config.hash_length as usize];
 
-            let store_data = if comp_chunk.cdata.len() > comp_chunk.data.len() {
-                &comp_chunk.data
+            // Store chunk uncompressed if compression does not make it smaller
+            let (compression, store_data) = if comp_chunk.cdata.len() >= comp_chunk.data.len() {
+                (Compression::None, &comp_chunk.data)
             } else {
-                &comp_chunk.cdata
+                (comp_chunk.compression, &comp_chunk.cdata)
             };
 
             debug!(
-                "Chunk {}, '{}', offset: {}, size: {}, compressed to: {}",
+                "Chunk {}, '{}', offset: {}, size: {}, compressed to: {} ({})",
                 total_unique_chunks,
                 HexSlice::new(&hash),
                 comp_chunk.offset,
                 size_to_str(comp_chunk.data.len()),
                 size_to_str(store_data.len()),
+                compression,
             );
 
             total_unique_chunks += 1;
@@ -90,6 +150,14 @@ fn chunk_into_file(
                 source_size: comp_chunk.data.len() as u32,
                 archive_offset,
                 archive_size: store_data.len() as u32,
+                // Uncompressed chunks are recognized by their size
+                compression: if compression == archive_compression
+                    || compression == Compression::None
+                {
+                    SingularPtrField::none()
+                } else {
+                    SingularPtrField::some(compression.into())
+                },
                 unknown_fields: std::default::Default::default(),
                 cached_size: std::default::Default::default(),
             });
@@ -102,7 +170,21 @@ fn chunk_into_file(
             // Read source from file
             let mut src_file = File::open(&input_path)
                 .chain_err(|| format!("unable to open input file ({})", input_path.display()))?;
-            let mut chunker = Chunker::new(chunker_params.clone(), &mut src_file);
+            // A pipe given as input file can not be split into regions
+            let seekable = src_file.seek(SeekFrom::Current(0)).is_ok();
+            if config.parallel_chunking && !seekable {
+                warn!("Input file is not seekable, falling back to serial chunking");
+            }
+            let mut chunker = if config.parallel_chunking && seekable {
+                info!("Chunking input file using {} threads...", pool.max_count());
+                let boundaries = find_chunk_boundaries(&chunker_params, pool.max_count(), || {
+                    File::open(&input_path)
+                })
+                .chain_err(|| format!("unable to chunk input file ({})", input_path.display()))?;
+                Chunker::with_boundaries(chunker_params.clone(), &mut src_file, boundaries)
+            } else {
+                Chunker::new(chunker_params.clone(), &mut src_file)
+            };
             let (tmp_file_size, tmp_file_hash, tmp_chunks) = unique_compressed_chunks(
                 &mut chunker,
                 hasher,
@@ -147,6 +229,11 @@ fn chunk_into_file(
 }
 
 pub fn run(config: &CompressConfig, pool: &ThreadPool) -> Result<()> {
+    let signing_key = match config.sign_key {
+        Some(ref path) => Some(signing::load_signing_key(path)?),
+        None => None,
+    };
+
     let mut output_file = OpenOptions::new()
         .write(true)
         .read(true)
@@ -164,8 +251,18 @@ pub fn run(config: &CompressConfig, pool: &ThreadPool) -> Result<()> {
         .open(&config.temp_file)
         .chain_err(|| "unable to create temporary chunk file")?;
 
+    let zstd_dictionary = match (config.zstd_dictionary_size, &config.input) {
+        (Some(size), Some(input_path)) => Some(Arc::new(ZstdDictionary::new(
+            train_zstd_dictionary(&config, input_path, size)?,
+            &config.compressions,
+        ))),
+        (Some(_), None) => bail!("zstd dictionary requires an input file"),
+        (None, _) => None,
+    };
+
     // Generate chunks and store to a temp file
-    let chunk_file_descriptor = chunk_into_file(&config, pool, &mut tmp_chunk_file)?;
+    let chunk_file_descriptor =
+        chunk_into_file(&config, pool, &mut tmp_chunk_file, zstd_dictionary.clone())?;
 
     // Store header to output file
     let file_header = chunk_dictionary::ChunkDictionary {
@@ -177,7 +274,7 @@ pub fn run(config: &CompressConfig, pool: &ThreadPool) -> Result<()> {
         application_version: PKG_VERSION.to_string(),
         chunk_descriptors: RepeatedField::from_vec(chunk_file_descriptor.chunk_descriptors),
         source_checksum: chunk_file_descriptor.file_hash,
-        chunk_compression: SingularPtrField::some(config.compression.into()),
+        chunk_compression: SingularPtrField::some(config.compressions[0].into()),
         source_total_size: chunk_file_descriptor.total_file_size as u64,
         chunker_params: SingularPtrField::some(chunk_dictionary::ChunkerParameters {
             chunk_filter_bits: config.chunk_filter_bits,
@@ -185,19 +282,45 @@ pub fn run(config: &CompressConfig, pool: &ThreadPool) -> Result<()> {
             max_chunk_size: config.max_chunk_size as u32,
             hash_window_size: config.hash_window_size as u32,
             chunk_hash_length: config.hash_length as u32,
+            chunking_algorithm: match config.chunking_algorithm {
+                ChunkingAlgorithm::BuzHash => ChunkerParameters_ChunkingAlgorithm::BUZHASH,
+                ChunkingAlgorithm::FixedSize => ChunkerParameters_ChunkingAlgorithm::FIXED_SIZE,
+                ChunkingAlgorithm::FastCdc => ChunkerParameters_ChunkingAlgorithm::FASTCDC,
+            },
             unknown_fields: std::default::Default::default(),
             cached_size: std::default::Default::default(),
         }),
+        signature_type: if signing_key.is_some() {
+            ChunkDictionary_SignatureType::ED25519
+        } else {
+            ChunkDictionary_SignatureType::NONE
+        },
+        zstd_dictionary: zstd_dictionary.map_or_else(Vec::new, |dict| dict.data().to_vec()),
         unknown_fields: std::default::Default::default(),
         cached_size: std::default::Default::default(),
     };
 
     // Copy chunks from temporary chunk tile to the output one
-    let header_buf = archive::build_header(&file_header, None)?;
-
-    output_file
-        .write_all(&header_buf)
-        .chain_err(|| "failed to write header")?;
+    match config.detached_header {
+        Some(ref header_path) => {
+            // Chunk data is placed at the start of output
+            let header_buf = archive::build_header(&file_header, Some(0), signing_key.as_ref())?;
+            OpenOptions::new()
+                .write(true)
+                .create(config.force_create)
+                .truncate(config.force_create)
+                .create_new(!config.force_create)
+                .open(header_path)
+                .and_then(|mut header_file| header_file.write_all(&header_buf))
+                .chain_err(|| format!("failed to write header ({})", header_path.display()))?;
+        }
+        None => {
+            let header_buf = archive::build_header(&file_header, None, signing_key.as_ref())?;
+            output_file
+                .write_all(&header_buf)
+                .chain_err(|| "failed to write header")?;
+        }
+    }
     tmp_chunk_file
         .seek(SeekFrom::Start(0))
         .chain_err(|| "failed to seek")?;
@@ -206,12 +329,25 @@ pub fn run(config: &CompressConfig, pool: &ThreadPool) -> Result<()> {
     drop(tmp_chunk_file);
     fs::remove_file(&config.temp_file).chain_err(|| "unable to remove temporary file")?;
 
-    output_file
-        .seek(SeekFrom::Start(0))
-        .chain_err(|| "failed to seek")?;
-
-    info!("Created archive {}", config.output.display());
-    info_cmd::print_archive_backend(output_file)
+    let header_file = match config.detached_header {
+        Some(ref header_path) => {
+            info!(
+                "Created archive header {} and chunk data {}",
+                header_path.display(),
+                config.output.display()
+            );
+            File::open(header_path)
+                .chain_err(|| format!("unable to open {}", header_path.display()))?
+        }
+        None => {
+            output_file
+                .seek(SeekFrom::Start(0))
+                .chain_err(|| "failed to seek")?;
+            info!("Created archive {}", config.output.display());
+            output_file
+        }
+    };
+    info_cmd::print_archive_backend(header_file)
         .chain_err(|| "failed to print archive information")?;
 
     Ok(())
@@ -1,23 +1,77 @@
+use blake2::{Blake2b, Digest};
 use lzma::LzmaWriter;
+use std::io;
 use std::io::prelude::*;
+use std::sync::Arc;
 
 use crate::chunk_dictionary;
 use crate::chunk_dictionary::ChunkCompression_CompressionType;
+use crate::chunker_utils::HashBuf;
 use crate::errors::*;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Compression {
     None,
     LZMA(u32),
     ZSTD(u32),
+    LZ4(u32),
+    Brotli(u32),
 }
 
+// Zstd dictionary prepared for decompression, and for compression at the zstd
+// levels in use, to avoid loading the dictionary for every chunk.
+pub struct ZstdDictionary {
+    data: Vec<u8>,
+    ddict: zstd_safe::DDict<'static>,
+    cdicts: Vec<(u32, zstd_safe::CDict<'static>)>,
+}
+
+impl ZstdDictionary {
+    pub fn new(data: Vec<u8>, compressions: &[Compression]) -> Self {
+        let mut cdicts = Vec::new();
+        for compression in compressions {
+            if let Compression::ZSTD(level) = compression {
+                if cdicts.iter().all(|(cdict_level, _)| cdict_level != level) {
+                    cdicts.push((*level, zstd_safe::create_cdict(&data, *level as i32)));
+                }
+            }
+        }
+        ZstdDictionary {
+            ddict: zstd_safe::create_ddict(&data),
+            cdicts,
+            data,
+        }
+    }
+
+    // Get the raw dictionary
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn cdict(&self, level: u32) -> Option<&zstd_safe::CDict<'static>> {
+        self.cdicts
+            .iter()
+            .find(|(cdict_level, _)| *cdict_level == level)
+            .map(|(_, cdict)| cdict)
+    }
+}
+
+fn zstd_error(code: usize) -> Error {
+    format!("zstd error ({})", zstd_safe::get_error_name(code)).into()
+}
+
+// Buffer size and window size (log2) used for brotli
+const BROTLI_BUFFER_SIZE: usize = 4096;
+const BROTLI_LG_WINDOW_SIZE: u32 = 22;
+
 impl std::fmt::Display for Compression {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Compression::LZMA(ref level) => write!(f, "LZMA({})", level),
             Compression::ZSTD(ref level) => write!(f, "ZSTD({})", level),
-            Compression::None => write!(f, "None)"),
+            Compression::LZ4(ref level) => write!(f, "LZ4({})", level),
+            Compression::Brotli(ref level) => write!(f, "Brotli({})", level),
+            Compression::None => write!(f, "None"),
         }
     }
 }
@@ -31,6 +85,12 @@ impl From<chunk_dictionary::ChunkCompression> for Compression {
             ChunkCompression_CompressionType::ZSTD => {
                 Compression::ZSTD(compression.compression_level)
             }
+            ChunkCompression_CompressionType::LZ4 => {
+                Compression::LZ4(compression.compression_level)
+            }
+            ChunkCompression_CompressionType::BROTLI => {
+                Compression::Brotli(compression.compression_level)
+            }
             ChunkCompression_CompressionType::NONE => Compression::None,
         }
     }
@@ -41,6 +101,8 @@ impl From<Compression> for chunk_dictionary::ChunkCompression {
         let (chunk_compression, chunk_compression_level) = match compression {
             Compression::LZMA(ref level) => (ChunkCompression_CompressionType::LZMA, *level),
             Compression::ZSTD(ref level) => (ChunkCompression_CompressionType::ZSTD, *level),
+            Compression::LZ4(ref level) => (ChunkCompression_CompressionType::LZ4, *level),
+            Compression::Brotli(ref level) => (ChunkCompression_CompressionType::BROTLI, *level),
             Compression::None => (ChunkCompression_CompressionType::NONE, 0),
         };
         chunk_dictionary::ChunkCompression {
@@ -53,8 +115,9 @@ impl From<Compression> for chunk_dictionary::ChunkCompression {
 }
 
 impl Compression {
-    // Compress a block of data with set compression
-    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+    // Compress a block of data with set compression. The dictionary is only
+    // used by zstd.
+    pub fn compress(self, data: &[u8], dictionary: Option<&ZstdDictionary>) -> Result<Vec<u8>> {
         match self {
             Compression::LZMA(ref level) => {
                 let mut result = vec![];
@@ -69,41 +132,325 @@ impl Compression {
                 Ok(result)
             }
             Compression::ZSTD(ref level) => {
+                let mut context = zstd_safe::create_cctx();
+                let mut result = vec![0; zstd_safe::compress_bound(data.len())];
+                let size = match (dictionary, dictionary.and_then(|dict| dict.cdict(*level))) {
+                    (_, Some(cdict)) => {
+                        zstd_safe::compress_using_cdict(&mut context, &mut result, data, cdict)
+                    }
+                    (Some(dict), None) => zstd_safe::compress_using_dict(
+                        &mut context,
+                        &mut result,
+                        data,
+                        dict.data(),
+                        *level as i32,
+                    ),
+                    (None, None) => {
+                        zstd_safe::compress_cctx(&mut context, &mut result, data, *level as i32)
+                    }
+                }
+                .map_err(zstd_error)
+                .chain_err(|| "failed compress with zstd")?;
+                result.truncate(size);
+                Ok(result)
+            }
+            Compression::LZ4(ref level) => {
+                let mut f = lz4::EncoderBuilder::new()
+                    .level(*level)
+                    .build(vec![])
+                    .chain_err(|| "failed to create lz4 compressor")?;
+                f.write_all(data).chain_err(|| "failed compress with lz4")?;
+                let (result, finish_result) = f.finish();
+                finish_result.chain_err(|| "failed to finish lz4 compression")?;
+                Ok(result)
+            }
+            Compression::Brotli(ref level) => {
+                // The stream is compressed and finished in one go, as the
+                // brotli writer only finishes the stream on drop and then
+                // ignores any error.
+                let params = brotli::enc::BrotliEncoderParams {
+                    quality: *level as i32,
+                    lgwin: BROTLI_LG_WINDOW_SIZE as i32,
+                    ..Default::default()
+                };
                 let mut result = vec![];
-                let data = data.to_vec();
-                zstd::stream::copy_encode(&data[..], &mut result, *level as i32)
-                    .chain_err(|| "failed compress with zstd")?;
+                brotli::BrotliCompress(&mut &data[..], &mut result, &params)
+                    .chain_err(|| "failed compress with brotli")?;
                 Ok(result)
             }
             Compression::None => Ok(data.to_vec()),
         }
     }
 
-    // Decompress a block of data using the set compression
-    pub fn decompress(self, input: Vec<u8>, output: &mut Vec<u8>) -> Result<()> {
+    // Compress a block of data with each of the given compressions and return
+    // the smallest result together with the compression used.
+    pub fn compress_smallest(
+        compressions: &[Compression],
+        data: &[u8],
+        dictionary: Option<&ZstdDictionary>,
+    ) -> Result<(Compression, Vec<u8>)> {
+        let mut smallest: Option<(Compression, Vec<u8>)> = None;
+        for compression in compressions {
+            let compressed = compression.compress(data, dictionary)?;
+            match smallest {
+                Some((_, ref smallest_data)) if smallest_data.len() <= compressed.len() => {}
+                _ => smallest = Some((*compression, compressed)),
+            }
+        }
+        smallest.chain_err(|| "no compression given")
+    }
+
+    // Decompress a block of data using the set compression and write the
+    // decompressed data to output. The dictionary must be the same as the one
+    // used when compressing.
+    pub fn decompress<W>(
+        self,
+        input: &[u8],
+        output: &mut W,
+        dictionary: Option<&ZstdDictionary>,
+    ) -> Result<()>
+    where
+        W: Write,
+    {
         match self {
             Compression::LZMA(_) => {
                 // Archived chunk is compressed with lzma
-                output.clear();
                 let mut f = LzmaWriter::new_decompressor(output)
                     .chain_err(|| "failed to create lzma decompressor")?;
-                f.write_all(&input)
+                f.write_all(input)
                     .chain_err(|| "failed to decompress using lzma")?;
                 f.finish()
                     .chain_err(|| "failed to finish lzma decompression")?;
             }
             Compression::ZSTD(_) => {
                 // Archived chunk is compressed with zstd
-                output.clear();
-                zstd::stream::copy_decode(&input[..], output)
-                    .chain_err(|| "failed to decompress using zstd")?;
+                let mut f = zstd::stream::read::Decoder::with_dictionary(
+                    input,
+                    dictionary.map_or(&[], ZstdDictionary::data),
+                )
+                .chain_err(|| "failed to create zstd decompressor")?;
+                io::copy(&mut f, output).chain_err(|| "failed to decompress using zstd")?;
+            }
+            Compression::LZ4(_) => {
+                // Archived chunk is compressed with lz4
+                let mut f =
+                    lz4::Decoder::new(input).chain_err(|| "failed to create lz4 decompressor")?;
+                io::copy(&mut f, output).chain_err(|| "failed to decompress using lz4")?;
+            }
+            Compression::Brotli(_) => {
+                // Archived chunk is compressed with brotli
+                let mut f = brotli::Decompressor::new(input, BROTLI_BUFFER_SIZE);
+                io::copy(&mut f, output).chain_err(|| "failed to decompress using brotli")?;
             }
             Compression::None => {
                 // Archived chunk is NOT compressed
-                *output = input;
+                output
+                    .write_all(input)
+                    .chain_err(|| "failed to write chunk data")?;
             }
         }
 
         Ok(())
     }
 }
+
+// Writer storing data to a buffer while hashing it
+struct HashingWriter<'a> {
+    hasher: Blake2b,
+    buffer: &'a mut Vec<u8>,
+}
+
+impl<'a> Write for HashingWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.input(buf);
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Decompressor of chunk data which can be held across many chunks. The zstd
+// context and the output buffer are reused for every chunk to avoid setting up
+// a new context and allocating a new buffer per chunk, and the data is hashed
+// while being decompressed.
+pub struct Decompressor {
+    zstd_context: zstd_safe::DCtx,
+    zstd_dictionary: Option<Arc<ZstdDictionary>>,
+    buffer: Vec<u8>,
+}
+
+impl Decompressor {
+    pub fn new(zstd_dictionary: Option<Arc<ZstdDictionary>>) -> Self {
+        Decompressor {
+            zstd_context: zstd_safe::create_dctx(),
+            zstd_dictionary,
+            buffer: Vec::new(),
+        }
+    }
+
+    // Decompress a chunk of the given decompressed size and return the (blake2)
+    // hash of the decompressed data. The decompressed data is available through
+    // data() until the next chunk is decompressed.
+    pub fn decompress(
+        &mut self,
+        compression: Compression,
+        input: &[u8],
+        size: usize,
+    ) -> Result<HashBuf> {
+        self.buffer.clear();
+        if let Compression::ZSTD(_) = compression {
+            // Zstd decompresses the whole chunk at once using the reused context
+            self.buffer.resize(size, 0);
+            let decompressed_size = match self.zstd_dictionary {
+                Some(ref dictionary) => zstd_safe::decompress_using_ddict(
+                    &mut self.zstd_context,
+                    &mut self.buffer,
+                    input,
+                    &dictionary.ddict,
+                ),
+                None => zstd_safe::decompress_dctx(&mut self.zstd_context, &mut self.buffer, input),
+            }
+            .map_err(zstd_error)
+            .chain_err(|| "failed to decompress using zstd")?;
+            if decompressed_size != size {
+                bail!(
+                    "unexpected size of decompressed chunk (expected: {}, got: {})",
+                    size,
+                    decompressed_size
+                );
+            }
+            return Ok(Blake2b::digest(&self.buffer).to_vec());
+        }
+
+        self.buffer.reserve(size);
+        let mut writer = HashingWriter {
+            hasher: Blake2b::new(),
+            buffer: &mut self.buffer,
+        };
+        compression.decompress(input, &mut writer, self.zstd_dictionary.as_deref())?;
+        Ok(writer.hasher.result().to_vec())
+    }
+
+    // Get the data of the last decompressed chunk
+    pub fn data(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+// Train a zstd dictionary of at most max_size bytes from the given samples
+pub fn train_zstd_dictionary(samples: &[Vec<u8>], max_size: usize) -> Result<Vec<u8>> {
+    zstd::dict::from_samples(samples, max_size).chain_err(|| "failed to train zstd dictionary")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smallest_compression() {
+        let data = vec![1; 4096];
+        let (compression, compressed) =
+            Compression::compress_smallest(&[Compression::None, Compression::ZSTD(3)], &data, None)
+                .unwrap();
+        assert_eq!(compression, Compression::ZSTD(3));
+        let mut decompressed = vec![];
+        compression
+            .decompress(&compressed, &mut decompressed, None)
+            .unwrap();
+        assert_eq!(decompressed, data);
+
+        // The first compression is kept when results are of same size
+        let (compression, _) =
+            Compression::compress_smallest(&[Compression::None, Compression::None], &data, None)
+                .unwrap();
+        assert_eq!(compression, Compression::None);
+        assert!(Compression::compress_smallest(&[], &data, None).is_err());
+    }
+
+    #[test]
+    fn compress_and_decompress() {
+        let data: Vec<u8> = (0..16384).map(|i| (i % 251) as u8).collect();
+        for compression in &[
+            Compression::None,
+            Compression::LZMA(6),
+            Compression::ZSTD(6),
+            Compression::LZ4(6),
+            Compression::Brotli(6),
+        ] {
+            let compressed = compression.compress(&data, None).unwrap();
+            let mut decompressed = vec![];
+            compression
+                .decompress(&compressed, &mut decompressed, None)
+                .unwrap();
+            assert_eq!(decompressed, data, "{}", compression);
+        }
+    }
+
+    #[test]
+    fn zstd_with_dictionary() {
+        let samples: Vec<Vec<u8>> = (0..200)
+            .map(|i| {
+                format!("sample {} of some repeated text with a number {}", i, i * 7).into_bytes()
+            })
+            .collect();
+        let dictionary = ZstdDictionary::new(
+            train_zstd_dictionary(&samples, 1024).unwrap(),
+            &[Compression::ZSTD(3)],
+        );
+        let data = b"sample 1000 of some repeated text with a number 7000".to_vec();
+        let compressed = Compression::ZSTD(3)
+            .compress(&data, Some(&dictionary))
+            .unwrap();
+        assert!(compressed.len() < Compression::ZSTD(3).compress(&data, None).unwrap().len());
+
+        let mut decompressed = vec![];
+        Compression::ZSTD(3)
+            .decompress(&compressed, &mut decompressed, Some(&dictionary))
+            .unwrap();
+        assert_eq!(decompressed, data);
+        assert!(Compression::ZSTD(3)
+            .decompress(&compressed, &mut vec![], None)
+            .is_err());
+
+        // Level without a prepared dictionary
+        let compressed = Compression::ZSTD(9)
+            .compress(&data, Some(&dictionary))
+            .unwrap();
+        let mut decompressor = Decompressor::new(Some(Arc::new(dictionary)));
+        decompressor
+            .decompress(Compression::ZSTD(9), &compressed, data.len())
+            .unwrap();
+        assert_eq!(decompressor.data(), &data[..]);
+    }
+
+    #[test]
+    fn reuse_decompressor() {
+        let mut decompressor = Decompressor::new(None);
+        for (compression, data) in &[
+            (Compression::ZSTD(3), vec![1; 8192]),
+            (Compression::None, vec![2; 100]),
+            (Compression::LZ4(3), vec![3; 4096]),
+        ] {
+            let compressed = compression.compress(data, None).unwrap();
+            let checksum = decompressor
+                .decompress(*compression, &compressed, data.len())
+                .unwrap();
+            assert_eq!(decompressor.data(), &data[..]);
+            assert_eq!(checksum, Blake2b::digest(data).to_vec());
+        }
+
+        // Chunk compressed as a zstd stream, without the size in frame header
+        let data = vec![4; 5000];
+        let compressed = zstd::stream::encode_all(&data[..], 3).unwrap();
+        decompressor
+            .decompress(Compression::ZSTD(3), &compressed, data.len())
+            .unwrap();
+        assert_eq!(decompressor.data(), &data[..]);
+        assert!(decompressor
+            .decompress(Compression::ZSTD(3), &compressed, data.len() - 1)
+            .is_err());
+    }
+}
@@ -1,5 +1,7 @@
+use bita::chunker::ChunkingAlgorithm;
 use bita::chunker_utils::HashBuf;
 use bita::compression::Compression;
+use bita::remote_archive_backend::RemoteOptions;
 use std::path::PathBuf;
 
 #[derive(Debug)]
@@ -11,27 +13,72 @@ pub struct CompressConfig {
     pub output: PathBuf,
     pub temp_file: PathBuf,
     pub hash_length: usize,
+    pub chunking_algorithm: ChunkingAlgorithm,
     pub chunk_filter_bits: u32,
     pub min_chunk_size: usize,
     pub max_chunk_size: usize,
     pub hash_window_size: usize,
     pub compression_level: u32,
-    pub compression: Compression,
+
+    // Compressions to try for each chunk, the smallest result is stored.
+    // The first one is the archive chunk compression.
+    pub compressions: Vec<Compression>,
+
+    // Train a zstd dictionary of this size, used for all zstd compressed chunks
+    pub zstd_dictionary_size: Option<usize>,
+
+    // Chunk the input file using multiple threads
+    pub parallel_chunking: bool,
+    pub sign_key: Option<PathBuf>,
+    pub detached_header: Option<PathBuf>,
 }
 
 #[derive(Debug)]
 pub struct CloneConfig {
     pub force_create: bool,
     pub input: String,
+    pub header: Option<String>,
     pub output: PathBuf,
     pub seed_stdin: bool,
     pub seed_files: Vec<PathBuf>,
+
+    // Glob patterns of files to use as seed
+    pub seed_patterns: Vec<String>,
+
+    // Directories to search (recursively) for files to use as seed
+    pub seed_dirs: Vec<PathBuf>,
+
+    // Scan seed files concurrently
+    pub parallel_seeds: bool,
+
+    // Chunk each seed file using multiple threads
+    pub parallel_chunking: bool,
+    pub seed_archive_dict: Option<String>,
+    pub chunk_cache: Option<PathBuf>,
+    pub skip_unchanged: bool,
+    pub in_place: bool,
+
+    // Directory of the spill file used while moving chunks in place
+    pub spill_dir: Option<PathBuf>,
+    pub journal: Option<PathBuf>,
+    pub resume: bool,
     pub header_checksum: Option<HashBuf>,
+    pub verify_key: Option<PathBuf>,
+    pub http: RemoteOptions,
+    pub mmap: bool,
 }
 
 #[derive(Debug)]
 pub struct InfoConfig {
     pub input: String,
+    pub http: RemoteOptions,
+}
+
+#[derive(Debug)]
+pub struct DiffConfig {
+    pub from: String,
+    pub to: String,
+    pub http: RemoteOptions,
 }
 
 #[derive(Debug)]
@@ -39,4 +86,5 @@ pub enum Config {
     Compress(CompressConfig),
     Clone(CloneConfig),
     Info(InfoConfig),
+    Diff(DiffConfig),
 }
@@ -0,0 +1,182 @@
+use log::*;
+use std::cmp;
+use std::collections::HashSet;
+
+use crate::config;
+use bita::archive::ChunkDescriptor;
+use bita::archive_reader::ArchiveReader;
+use bita::backend_registry::BackendRegistry;
+use bita::chunker_utils::HashBuf;
+use bita::errors::*;
+use bita::string_utils::*;
+
+fn open_archive(input: &str, registry: &BackendRegistry) -> Result<ArchiveReader> {
+    let mut archive_backend = registry.open(input)?;
+    ArchiveReader::try_init(&mut archive_backend, &mut Vec::new())
+}
+
+// Get the ranges in source, given by the source layout of an archive, which
+// are built from chunks not present in the given hash set. Adjacent ranges are
+// merged.
+fn changed_source_ranges(
+    source_layout: Vec<(u64, &ChunkDescriptor)>,
+    hash_length: usize,
+    present: &HashSet<HashBuf>,
+) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for (offset, descriptor) in source_layout {
+        if present.contains(&descriptor.checksum[..hash_length]) {
+            continue;
+        }
+        let end = offset + u64::from(descriptor.source_size);
+        match ranges.last_mut() {
+            Some(range) if range.1 == offset => range.1 = end,
+            _ => ranges.push((offset, end)),
+        }
+    }
+    ranges
+}
+
+fn percent_of(part: u64, total: u64) -> u64 {
+    if total == 0 {
+        0
+    } else {
+        part * 100 / total
+    }
+}
+
+pub fn run(config: &config::DiffConfig) -> Result<()> {
+    let registry = BackendRegistry::with_http(config.http.clone());
+    let from_archive = open_archive(&config.from, &registry)
+        .chain_err(|| format!("failed to open archive {}", config.from))?;
+    let to_archive = open_archive(&config.to, &registry)
+        .chain_err(|| format!("failed to open archive {}", config.to))?;
+
+    if from_archive.chunker_params != to_archive.chunker_params {
+        warn!(
+            "Archives use different chunker parameters, chunks of {} will not be found in a seed",
+            config.to
+        );
+    }
+
+    // Compare chunks by the shortest hash length used by the archives
+    let hash_length = cmp::min(from_archive.hash_length, to_archive.hash_length);
+    let from_chunks: HashSet<HashBuf> = from_archive
+        .chunk_hash_set()
+        .into_iter()
+        .map(|hash| hash[..hash_length].to_vec())
+        .collect();
+
+    let mut shared_chunks = 0;
+    let mut shared_size: u64 = 0;
+    let mut download_chunks = 0;
+    let mut download_size: u64 = 0;
+    let mut download_source_size: u64 = 0;
+    for descriptor in &to_archive.chunk_descriptors {
+        if from_chunks.contains(&descriptor.checksum[..hash_length]) {
+            shared_chunks += 1;
+            shared_size += u64::from(descriptor.source_size);
+        } else {
+            download_chunks += 1;
+            download_size += u64::from(descriptor.archive_size);
+            download_source_size += u64::from(descriptor.source_size);
+        }
+    }
+
+    let changed_ranges =
+        changed_source_ranges(to_archive.source_layout(), hash_length, &from_chunks);
+    let changed_size: u64 = changed_ranges.iter().map(|(start, end)| end - start).sum();
+
+    info!("From: {}", config.from);
+    info!(
+        "  Chunks: {} (unique: {}), source size: {}",
+        from_archive.total_chunks(),
+        from_archive.unique_chunks(),
+        size_to_str(from_archive.source_total_size)
+    );
+    info!("To: {}", config.to);
+    info!(
+        "  Chunks: {} (unique: {}), source size: {}",
+        to_archive.total_chunks(),
+        to_archive.unique_chunks(),
+        size_to_str(to_archive.source_total_size)
+    );
+    info!("Shared:");
+    info!(
+        "  Chunks: {} of {} unique chunks ({}%)",
+        shared_chunks,
+        to_archive.unique_chunks(),
+        percent_of(shared_chunks, to_archive.unique_chunks() as u64)
+    );
+    info!("  Size: {}", size_to_str(shared_size));
+    info!("Download:");
+    info!(
+        "  Chunks: {} ({} decompressed)",
+        download_chunks,
+        size_to_str(download_source_size)
+    );
+    info!("  Chunk data: {}", size_to_str(download_size));
+    info!("  Header: {}", size_to_str(to_archive.header_size));
+    info!(
+        "  Total: {} ({}% of archive)",
+        size_to_str(download_size + to_archive.header_size as u64),
+        percent_of(
+            download_size + to_archive.header_size as u64,
+            to_archive.compressed_size() + to_archive.header_size as u64
+        )
+    );
+    info!(
+        "Changed source ranges: {} ({}, {}% of source)",
+        changed_ranges.len(),
+        size_to_str(changed_size),
+        percent_of(changed_size, to_archive.source_total_size)
+    );
+    for (start, end) in &changed_ranges {
+        info!("  {}-{} ({})", start, end, size_to_str(end - start));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(checksum: &[u8], source_size: u32) -> ChunkDescriptor {
+        ChunkDescriptor {
+            checksum: checksum.to_vec(),
+            archive_size: source_size,
+            archive_offset: 0,
+            source_size,
+            compression: None,
+        }
+    }
+
+    #[test]
+    fn merge_adjacent_changed_ranges() {
+        let (a, b, c, d) = (
+            descriptor(b"aaaa", 10),
+            descriptor(b"bbbb", 20),
+            descriptor(b"cccc", 5),
+            descriptor(b"dddd", 8),
+        );
+        let layout = vec![(0, &a), (10, &b), (30, &c), (35, &a), (45, &d)];
+        let present: HashSet<HashBuf> = vec![b"aa".to_vec()].into_iter().collect();
+        assert_eq!(
+            changed_source_ranges(layout.clone(), 2, &present),
+            vec![(10, 35), (45, 53)]
+        );
+        assert_eq!(
+            changed_source_ranges(layout, 2, &HashSet::new()),
+            vec![(0, 53)]
+        );
+    }
+
+    #[test]
+    fn percent_of_total() {
+        assert_eq!(percent_of(0, 0), 0);
+        assert_eq!(percent_of(5, 0), 0);
+        assert_eq!(percent_of(1, 3), 33);
+        assert_eq!(percent_of(10, 10), 100);
+    }
+}
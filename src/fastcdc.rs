@@ -0,0 +1,146 @@
+#![cfg_attr(feature = "cargo-clippy", allow(clippy::unreadable_literal))]
+use std::cmp;
+
+// Table of random values used by the gear hash, one for each byte value
+#[rustfmt::skip]
+static GEAR_TABLE: &'static [u64] = &[
+    0x6fe1c3c3f47be772, 0xef482c2109f2668a, 0x13eb0fb5ae8a5648, 0xb20718f1b15ac49e,
+    0xb3721174b11c3821, 0x783df9fca1f89383, 0xa38e34022834befe, 0xd0155c664dab1abf,
+    0x0ba133528fdfb8cd, 0x4babd0ebf5af8f59, 0xa18d5535c4e934c7, 0x03820b79e8873157,
+    0xdaf498803db70da1, 0x5f9f54f4e768b177, 0xad692d617547ed9c, 0x077b7b3110cd2364,
+    0x296a4003ec4771da, 0x0bf6b1a30dc24750, 0xcdab1f6359768635, 0x64ed7a6f50290d30,
+    0xb9b1e4596d3a63a4, 0x64a6132250f15890, 0xa690e3d4ae422805, 0x2640e0b7bc44098e,
+    0xdfa6fb910367c0ee, 0x6bec4bec07b73df6, 0x45f5690699bbb5c3, 0x66ddfe68a0f2be02,
+    0x7ad1db48d39b61fd, 0xe4a5dee9718e3a9d, 0x50286e85b467c9b0, 0x923c3820da3ad382,
+    0xc87de8ed013b2af6, 0x447477279ff25ae5, 0x60184148570cc98d, 0xc7e869c9cd38ae42,
+    0xd9144c86818585ab, 0xc06cde7400445817, 0x04217ff176063605, 0x5dc152af6e72e546,
+    0x1da1fa3c2bbeca5e, 0xe639a65279fcbc55, 0xb59dbd9a41073f2f, 0xcccc502880d8510a,
+    0x5b78270e53ee3b64, 0x2987da8e7020fce0, 0x48d58e2d506ab775, 0x9f6ef3c975e82e36,
+    0xd9f9ca865930c6dd, 0xfc658b1cbd106426, 0xf3c82ea145e37604, 0x0f244200ec9ee89d,
+    0xac39c541c1473bba, 0x5d1c306a0a4c93f1, 0x66941766183ecfb7, 0xa061cb7baffbeaad,
+    0xcaa0d65385c21d93, 0x8061349850677baf, 0xa162cb1fcddf96e4, 0x3838b8833f67746f,
+    0xbd508f496bae70c9, 0x275d02b9a32cd3e1, 0x0c22206f5750633d, 0x9bb5db970bc5eb73,
+    0x17937ad9ab1638ab, 0x5dee21e6a1443b81, 0xe4c2e7d2241f0da1, 0xacb4115732a9855a,
+    0xf580dcf6ed27ac2a, 0xe76a88b66b86fb18, 0xd0561115c91ab110, 0x680249a3ffc9eabf,
+    0x904e01889ebdb56e, 0x8f1bc16640f62301, 0xe8ff71ca44e2e615, 0x5b1622cb3c195706,
+    0xd698710f834fc05d, 0x40633fa4d7cb6733, 0x79067a03ae23d65d, 0x78491da880e633fb,
+    0xbc3ccd01fc1a38ec, 0xc79f09046bd60761, 0x254f2e5ba5e800fe, 0xb3dac84e09581bc3,
+    0x26162255574beed7, 0x942f49f7d709c662, 0x089878d1a1d62f74, 0xf1a12f717c416bb2,
+    0x645c5eacfe24d6c5, 0x2349c199de415ac3, 0x46b5b07b397255d6, 0x32dcb8da9517882a,
+    0x31bd9a02e4c4c280, 0xf387d0535f8d1ad0, 0x5d31d149da40a051, 0x74c625df4fbd0aff,
+    0xd12d3c49d158892d, 0xdc85f986d62d80d5, 0xcb520a440c88d83a, 0xee574af29057065e,
+    0x486a59fa84ef7ce9, 0x860b66974fbbf348, 0x3aa002972534218d, 0x16394406b2ff985b,
+    0x267e8fb101d650c6, 0xffdf21d16e4b5354, 0xa9a7049e906cd1a6, 0xcd39b4b759c8ddfe,
+    0x14d8298a31851a99, 0x8e5f91b43c15b9d0, 0x5620bc72c24a5ef0, 0x8c8200f77e0b4da6,
+    0x9be88b3c2adc44e6, 0xcee68e3c6a622142, 0x94cdbbf11854b76c, 0xeaa4c678cdffd53e,
+    0x69fae8ff2495387a, 0xce239d2b0aff2c1f, 0x4e270b78b3ccf845, 0xdfa512d02d0bfa7a,
+    0xf693853f244a9a80, 0xb4e7af216b0479e5, 0xc0bfe4bfbb7471fa, 0x85741abc5c237212,
+    0x5c9b3b9ed89834e4, 0x4c46c7b352e57850, 0x0585c2e5828d12f4, 0x38395f05ff60578b,
+    0x672bdfb849316fcc, 0xb3718449198de14d, 0x76bef81df41d1790, 0xc781c751bfe05992,
+    0x3aa90ec42bcc78ad, 0xaf8506571ec602b1, 0x496edb6aa6a9bd26, 0x28f3e24f212149c0,
+    0xb10d7e8b3b6b74cf, 0x7b82d67539f64c19, 0x162d838a154634e0, 0xbfb09a04789329bc,
+    0xdd5c88dd915c25f5, 0xecc28dece6865d11, 0x9da402be0cd34d17, 0xaaf5ecc64fcf7f5b,
+    0x3eba303a86176ebd, 0xc6efe1ae3126e3db, 0x7b79c515623c3397, 0x290dfd0f1f1a2d9a,
+    0x953e53ddf25c9f71, 0xf5540f2c03d2bcf5, 0x590727ccf5e60879, 0xad87ea8ea9587cd1,
+    0x7951026bc5cfb888, 0xb16bb9e8c036e81e, 0x69bab2cd01cce340, 0x93ed376786764306,
+    0xd5338e6063511934, 0xaf14577cc166bc60, 0x58384a1a72b51ee5, 0x32ed737641ecec01,
+    0xee913a963a78e126, 0xa679cda093185928, 0x2c29bb23fc2cba55, 0xa8397623529de075,
+    0x5b6438748dc90fd1, 0x813b088ca115befc, 0x701dd8ba5193046d, 0xe738743830dc4b04,
+    0x1b14561fb97c3a37, 0x8e3608f7be439497, 0xb3e80320b17d3d49, 0x3eea05460330dd93,
+    0xd85c5a3764904e34, 0x65177de3f07b773f, 0x3f5535ca9a60fa46, 0xc7fd8cf20fb65df0,
+    0x5acdc09a1fc3b398, 0x227051429f9424f4, 0xb1b62d1ecc5cdd92, 0x85f9ebfae71854f1,
+    0x0d1811ff6f44689c, 0xf24ef9461e5afa5e, 0x6f618e3a2bbb040d, 0xbbe598bd77a4da7b,
+    0x3d942cb51c27c634, 0xd626884d40cabf99, 0x8cb1005a085ca3db, 0xaea3abf6c633280f,
+    0xba453a3c458638bd, 0xa1a7b3faed066d93, 0xee95bf8781f2f423, 0x187e742acc67743a,
+    0xf40eb7e65c52337a, 0x53400f96a20281aa, 0x3798e7343ce4f1dd, 0x3b2d6a02a9b6188a,
+    0x92a9bcbc6d63e5fe, 0x762a2272a4a89bc3, 0x159fa67cdb25ab68, 0x04ff547ca4241445,
+    0xfbe2da9a90d33c9f, 0xacf7cca861a98cd2, 0x78afb5494fe7c0ee, 0x03aa5eb7136f50be,
+    0x5e9e7eec870d5b20, 0x47095c6b9a500f48, 0x3e716685a1112f55, 0x0317ea90cd44edff,
+    0xe9bd2daa2aa0975b, 0x32cd6b690a6672e7, 0xfe73aab6a37b8b4c, 0x5f2fdaa1274e1f45,
+    0x55019e4c595fce77, 0x88518ec55a062f27, 0x3d786f5654a3c9fb, 0x99da40c5549c5d9a,
+    0x98264d1616a1083e, 0x5d9585c924cb7557, 0x1f992245c9b2dfe4, 0xcdcb0cb75e0ac9c2,
+    0xbd3df2a44926daa5, 0x058687014ee505fe, 0x7294f86f085d3df7, 0x72b358c62af96f10,
+    0xd4fad8505a693f1e, 0x53fa2ebbd6f4dcca, 0x182d083f881c4000, 0x4b5d93d03d38e2ad,
+    0x57c2adc1f15c6fb8, 0xcdc2ca77887614f6, 0x28bdfbf717582099, 0xb563537952339b93,
+    0x500e33cc3b6cba32, 0x8549542d0d6aed66, 0xb793302017311b31, 0x59d9543b16f264f2,
+    0x62d9e3c76beaf775, 0x8a5acd301ac0d2e6, 0x7225029c0a390146, 0x4ca00e7b99cbd1e6,
+    0x50f810fa298ee720, 0x87695a0931716be9, 0x334b705d865c7a62, 0xd488b365e3990e76,
+    0x38cbfa7e67b9d248, 0x9ae981c5ec20b3ad, 0x87f131fbbddb2c03, 0xad4cc2ab0cafae50,
+    0x87d6f20a26e2108a, 0xc10e317300ba9a37, 0x63886e7025b5273a, 0xb3d0d4940c70612a,
+    0x5db3a24343ab3a46, 0x2916759d4361ceab, 0x216019b72b7bfc33, 0xda7f69fa774c4fc1,
+];
+
+// Number of mask bits added/removed before/after the average chunk size is
+// reached (normalization level). Makes chunk sizes gather around the average.
+const NORMALIZATION_LEVEL: u32 = 2;
+
+// Create a mask of the given number of (high) bits
+fn mask(bits: u32) -> u64 {
+    let bits = cmp::min(cmp::max(bits, 1), 63);
+    !0 << (64 - bits)
+}
+
+// Content defined chunking using the FastCDC algorithm. A gear hash is
+// calculated from the minimum chunk size and a chunk boundary is found when
+// the masked hash is zero. A harder mask is used before the average chunk size
+// and an easier one after it.
+#[derive(Clone)]
+pub struct FastCdc {
+    hash: u64,
+    mask_small: u64,
+    mask_large: u64,
+    min_chunk_size: usize,
+    avg_chunk_size: usize,
+    max_chunk_size: usize,
+}
+
+impl FastCdc {
+    pub fn new(avg_chunk_size: usize, min_chunk_size: usize, max_chunk_size: usize) -> Self {
+        let bits = (avg_chunk_size as u64).trailing_zeros();
+        FastCdc {
+            hash: 0,
+            mask_small: mask(bits + NORMALIZATION_LEVEL),
+            mask_large: mask(bits.saturating_sub(NORMALIZATION_LEVEL)),
+            min_chunk_size,
+            avg_chunk_size,
+            max_chunk_size,
+        }
+    }
+
+    // Reset the hash before scanning for the next chunk
+    pub fn reset(&mut self) {
+        self.hash = 0;
+    }
+
+    // Scan for a chunk boundary in chunk data, starting at scan_start. The chunk
+    // data should start at the chunk start and scan_start should be where the
+    // last scan ended. Returns the chunk size if a boundary is found.
+    pub fn find_boundary(&mut self, chunk_data: &[u8], scan_start: usize) -> Option<usize> {
+        let mut index = cmp::max(scan_start, self.min_chunk_size);
+
+        // Use the harder mask until the average chunk size is reached
+        let end = cmp::min(self.avg_chunk_size, chunk_data.len());
+        while index < end {
+            self.hash = (self.hash << 1).wrapping_add(GEAR_TABLE[chunk_data[index] as usize]);
+            index += 1;
+            if self.hash & self.mask_small == 0 {
+                return Some(index);
+            }
+        }
+
+        let end = cmp::min(self.max_chunk_size, chunk_data.len());
+        while index < end {
+            self.hash = (self.hash << 1).wrapping_add(GEAR_TABLE[chunk_data[index] as usize]);
+            index += 1;
+            if self.hash & self.mask_large == 0 {
+                return Some(index);
+            }
+        }
+
+        if chunk_data.len() >= self.max_chunk_size {
+            Some(self.max_chunk_size)
+        } else {
+            None
+        }
+    }
+}
@@ -13,19 +13,20 @@ impl ArchiveBackend for File {
             .chain_err(|| "failed to read archive file")?;
         Ok(())
     }
-    fn read_in_chunks<F: FnMut(Vec<u8>) -> Result<()>>(
+    fn read_in_chunks(
         &mut self,
         start_offset: u64,
         chunk_sizes: &[u64],
-        mut chunk_callback: F,
+        chunk_callback: &mut dyn FnMut(&[u8]) -> Result<()>,
     ) -> Result<()> {
         self.seek(SeekFrom::Start(start_offset))
             .chain_err(|| "failed to seek archive file")?;
+        let mut buf: Vec<u8> = Vec::new();
         for chunk_size in chunk_sizes {
-            let mut buf: Vec<u8> = vec![0; *chunk_size as usize];
+            buf.resize(*chunk_size as usize, 0);
             self.read_exact(&mut buf[..])
                 .chain_err(|| "failed to read archive file")?;
-            chunk_callback(buf)?;
+            chunk_callback(&buf)?;
         }
         Ok(())
     }
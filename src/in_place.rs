@@ -0,0 +1,335 @@
+use std::collections::VecDeque;
+
+// Planning of an in-place clone, where chunks already present in the output
+// file are moved to their place in the target. A chunk must not be
+// overwritten before it has been read, so moves are ordered by their
+// dependencies. Moves which depend on each other in a cycle are broken up by
+// storing one of the chunks in a spill file.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkMove {
+    // Offset of chunk in the current output
+    pub source: u64,
+    pub size: u64,
+
+    // Offsets where the chunk should be placed in target
+    pub targets: Vec<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MoveOp {
+    // Read chunk at source and write it to all targets
+    Copy {
+        source: u64,
+        size: u64,
+        targets: Vec<u64>,
+    },
+    // Read chunk at source and store it at offset in the spill file
+    Spill {
+        source: u64,
+        size: u64,
+        spill_offset: u64,
+    },
+    // Read chunk from offset in the spill file and write it to all targets
+    Restore {
+        spill_offset: u64,
+        size: u64,
+        targets: Vec<u64>,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct MovePlan {
+    pub ops: Vec<MoveOp>,
+
+    // Total size of the spill file needed to execute the plan
+    pub spill_size: u64,
+}
+
+fn overlaps(start1: u64, size1: u64, start2: u64, size2: u64) -> bool {
+    start1 < start2 + size2 && start2 < start1 + size1
+}
+
+// Build a plan for moving chunks inside of the output file.
+// The source ranges of the given moves must not overlap each other, and the
+// same goes for the target ranges (as the chunks are placed in sequence in
+// both the current output and the target).
+pub fn plan_moves(moves: Vec<ChunkMove>) -> MovePlan {
+    // Targets where the chunk already is in place need no write
+    let moves: Vec<ChunkMove> = moves
+        .into_iter()
+        .map(|mut chunk_move| {
+            let source = chunk_move.source;
+            chunk_move.targets.retain(|target| *target != source);
+            chunk_move
+        })
+        .filter(|chunk_move| !chunk_move.targets.is_empty())
+        .collect();
+
+    let mut by_source: Vec<usize> = (0..moves.len()).collect();
+    by_source.sort_by_key(|index| moves[*index].source);
+
+    // A move which writes to a target must wait for all moves with a source
+    // overlapping that target to be read.
+    let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); moves.len()];
+    let mut dependencies: Vec<Vec<usize>> = vec![Vec::new(); moves.len()];
+    for (index, chunk_move) in moves.iter().enumerate() {
+        for target in &chunk_move.targets {
+            // Find the first move with a source which may overlap the target
+            let mut pos = match by_source.binary_search_by_key(target, |i| moves[*i].source) {
+                Ok(pos) => pos,
+                Err(pos) => pos,
+            };
+            if pos > 0 {
+                let prev = &moves[by_source[pos - 1]];
+                if overlaps(prev.source, prev.size, *target, chunk_move.size) {
+                    pos -= 1;
+                }
+            }
+            while pos < by_source.len() && moves[by_source[pos]].source < target + chunk_move.size {
+                let other = by_source[pos];
+                // A move overlapping itself is fine since the chunk is read
+                // before it is written.
+                if other != index {
+                    dependents[other].push(index);
+                    dependencies[index].push(other);
+                }
+                pos += 1;
+            }
+        }
+    }
+
+    let mut waiting_for: Vec<usize> = dependencies.iter().map(|deps| deps.len()).collect();
+    let mut released = vec![false; moves.len()];
+    let mut done = vec![false; moves.len()];
+    let mut spilled: Vec<Option<u64>> = vec![None; moves.len()];
+    let mut remaining = moves.len();
+    let mut ready: VecDeque<usize> = by_source
+        .iter()
+        .cloned()
+        .filter(|index| waiting_for[*index] == 0)
+        .collect();
+    let mut plan = MovePlan::default();
+
+    // State of the walk for cycles, kept between spills
+    let mut path: Vec<usize> = Vec::new();
+    let mut on_path = vec![false; moves.len()];
+    let mut next_dependency = vec![0; moves.len()];
+    let mut first_remaining = 0;
+
+    // The source of a chunk has been read and is free to be overwritten
+    let release = |index: usize,
+                   released: &mut Vec<bool>,
+                   waiting_for: &mut Vec<usize>,
+                   ready: &mut VecDeque<usize>| {
+        released[index] = true;
+        for dependent in &dependents[index] {
+            waiting_for[*dependent] -= 1;
+            if waiting_for[*dependent] == 0 {
+                ready.push_back(*dependent);
+            }
+        }
+    };
+
+    while remaining > 0 {
+        while let Some(index) = ready.pop_front() {
+            let chunk_move = &moves[index];
+            done[index] = true;
+            remaining -= 1;
+            match spilled[index] {
+                Some(spill_offset) => plan.ops.push(MoveOp::Restore {
+                    spill_offset,
+                    size: chunk_move.size,
+                    targets: chunk_move.targets.clone(),
+                }),
+                None => {
+                    plan.ops.push(MoveOp::Copy {
+                        source: chunk_move.source,
+                        size: chunk_move.size,
+                        targets: chunk_move.targets.clone(),
+                    });
+                    release(index, &mut released, &mut waiting_for, &mut ready);
+                }
+            }
+        }
+        if remaining == 0 {
+            break;
+        }
+
+        // All remaining moves are waiting for each other. Walk the
+        // dependencies backwards until a move already on the walked path is
+        // found, that move is part of a cycle and is spilled to break the
+        // cycle. The path leading up to the cycle is kept for the next walk.
+        while let Some(top) = path.last() {
+            if !done[*top] {
+                break;
+            }
+            on_path[*top] = false;
+            path.pop();
+        }
+        if path.is_empty() {
+            while done[first_remaining] {
+                first_remaining += 1;
+            }
+            path.push(first_remaining);
+            on_path[first_remaining] = true;
+        }
+        let index = loop {
+            let current = *path.last().expect("path");
+            let deps = &dependencies[current];
+            while released[deps[next_dependency[current]]] {
+                next_dependency[current] += 1;
+            }
+            let dependency = deps[next_dependency[current]];
+            if on_path[dependency] {
+                break dependency;
+            }
+            path.push(dependency);
+            on_path[dependency] = true;
+        };
+        // Drop the cycle from path as its moves are no longer blocked by the
+        // spilled move.
+        while let Some(node) = path.pop() {
+            on_path[node] = false;
+            if node == index {
+                break;
+            }
+        }
+        let chunk_move = &moves[index];
+        spilled[index] = Some(plan.spill_size);
+        plan.ops.push(MoveOp::Spill {
+            source: chunk_move.source,
+            size: chunk_move.size,
+            spill_offset: plan.spill_size,
+        });
+        plan.spill_size += chunk_move.size;
+        release(index, &mut released, &mut waiting_for, &mut ready);
+    }
+
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Execute the plan on the given buffer
+    fn apply(plan: &MovePlan, buf: &mut Vec<u8>) {
+        let mut spill = vec![0; plan.spill_size as usize];
+        for op in &plan.ops {
+            match op {
+                MoveOp::Copy {
+                    source,
+                    size,
+                    targets,
+                } => {
+                    let data = buf[*source as usize..(source + size) as usize].to_vec();
+                    for target in targets {
+                        buf[*target as usize..(target + size) as usize].copy_from_slice(&data);
+                    }
+                }
+                MoveOp::Spill {
+                    source,
+                    size,
+                    spill_offset,
+                } => {
+                    spill[*spill_offset as usize..(spill_offset + size) as usize]
+                        .copy_from_slice(&buf[*source as usize..(source + size) as usize]);
+                }
+                MoveOp::Restore {
+                    spill_offset,
+                    size,
+                    targets,
+                } => {
+                    for target in targets {
+                        buf[*target as usize..(target + size) as usize].copy_from_slice(
+                            &spill[*spill_offset as usize..(spill_offset + size) as usize],
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn chunk_move(source: u64, size: u64, targets: &[u64]) -> ChunkMove {
+        ChunkMove {
+            source,
+            size,
+            targets: targets.to_vec(),
+        }
+    }
+
+    #[test]
+    fn in_place_chunks_are_skipped() {
+        let plan = plan_moves(vec![chunk_move(0, 4, &[0]), chunk_move(4, 4, &[4])]);
+        assert!(plan.ops.is_empty());
+        assert_eq!(plan.spill_size, 0);
+    }
+
+    #[test]
+    fn shift_without_spill() {
+        // Chunks shifted two bytes forward, must be moved back to front
+        let mut buf = b"AAAABBBBCCCC".to_vec();
+        let plan = plan_moves(vec![
+            chunk_move(0, 4, &[2]),
+            chunk_move(4, 4, &[6]),
+            chunk_move(8, 4, &[10]),
+        ]);
+        assert_eq!(plan.spill_size, 0);
+        buf.resize(14, 0);
+        apply(&plan, &mut buf);
+        assert_eq!(&buf[2..], b"AAAABBBBCCCC");
+    }
+
+    #[test]
+    fn swap_needs_spill() {
+        let mut buf = b"AAAABBBB".to_vec();
+        let plan = plan_moves(vec![chunk_move(0, 4, &[4]), chunk_move(4, 4, &[0])]);
+        assert_eq!(plan.spill_size, 4);
+        apply(&plan, &mut buf);
+        assert_eq!(&buf[..], b"BBBBAAAA");
+    }
+
+    #[test]
+    fn duplicated_chunk_and_rotation() {
+        // Rotate three chunks of different size and duplicate one of them
+        let mut buf = b"AABBBCCCC".to_vec();
+        let plan = plan_moves(vec![
+            chunk_move(0, 2, &[7, 9]),
+            chunk_move(2, 3, &[4]),
+            chunk_move(5, 4, &[0]),
+        ]);
+        buf.resize(11, 0);
+        apply(&plan, &mut buf);
+        assert_eq!(&buf[..], b"CCCCBBBAAAA");
+    }
+
+    #[test]
+    fn shuffled_chunks_with_many_cycles() {
+        // Shuffle a large number of chunks, giving many cycles to break
+        let count = 2000;
+        let size = 3;
+        let mut order: Vec<u64> = (0..count).collect();
+        let mut state: u64 = 12345;
+        for i in (1..order.len()).rev() {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1);
+            order.swap(i, (state >> 33) as usize % (i + 1));
+        }
+        let mut buf: Vec<u8> = (0..count * size).map(|v| (v / size) as u8).collect();
+        let expected: Vec<u8> = order
+            .iter()
+            .flat_map(|chunk| buf[(chunk * size) as usize..((chunk + 1) * size) as usize].to_vec())
+            .collect();
+        let plan = plan_moves(
+            order
+                .iter()
+                .enumerate()
+                .map(|(pos, chunk)| chunk_move(chunk * size, size, &[pos as u64 * size]))
+                .collect(),
+        );
+        apply(&plan, &mut buf);
+        assert_eq!(buf, expected);
+    }
+}
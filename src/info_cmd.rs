@@ -1,10 +1,11 @@
 use log::*;
-use std::fs::File;
+use std::collections::BTreeMap;
 
 use crate::config;
 use bita::archive_reader::{ArchiveBackend, ArchiveReader};
+use bita::backend_registry::BackendRegistry;
+use bita::chunker::ChunkingAlgorithm;
 use bita::errors::*;
-use bita::remote_archive_backend::RemoteReader;
 use bita::string_utils::*;
 
 pub fn print_archive_backend<T>(mut archive_backend: T) -> Result<()>
@@ -19,25 +20,79 @@ where
 pub fn print_archive(archive: &ArchiveReader) {
     info!("Archive: ");
     info!("  Version: {}", archive.created_by_app_version);
-    info!(
-        "  Chunk minimum size: {}",
-        size_to_str(archive.chunker_params.min_chunk_size),
-    );
-    info!(
-        "  Chunk maximum size: {}",
-        size_to_str(archive.chunker_params.max_chunk_size),
-    );
-    info!(
-        "  Chunk average target size: {} (mask: {:#b})",
-        size_to_str(archive.chunker_params.chunk_target_average()),
-        archive.chunker_params.filter_mask(),
-    );
+    match archive.chunker_params.algorithm {
+        ChunkingAlgorithm::BuzHash => {
+            info!(
+                "  Chunk minimum size: {}",
+                size_to_str(archive.chunker_params.min_chunk_size),
+            );
+            info!(
+                "  Chunk maximum size: {}",
+                size_to_str(archive.chunker_params.max_chunk_size),
+            );
+            info!(
+                "  Chunk average target size: {} (mask: {:#b})",
+                size_to_str(archive.chunker_params.chunk_target_average()),
+                archive.chunker_params.filter_mask(),
+            );
+        }
+        ChunkingAlgorithm::FixedSize => {
+            info!(
+                "  Chunk fixed size: {}",
+                size_to_str(archive.chunker_params.max_chunk_size),
+            );
+        }
+        ChunkingAlgorithm::FastCdc => {
+            info!("  Chunking algorithm: FastCDC");
+            info!(
+                "  Chunk minimum size: {}",
+                size_to_str(archive.chunker_params.min_chunk_size),
+            );
+            info!(
+                "  Chunk maximum size: {}",
+                size_to_str(archive.chunker_params.max_chunk_size),
+            );
+            info!(
+                "  Chunk average target size: {}",
+                size_to_str(archive.chunker_params.chunk_target_average()),
+            );
+        }
+    }
     info!("  Chunk compression: {}", archive.chunk_compression);
+    if archive
+        .chunk_descriptors
+        .iter()
+        .any(|descriptor| descriptor.compression.is_some())
+    {
+        // Chunks are compressed using different compressions
+        let mut chunks_per_compression: BTreeMap<String, usize> = BTreeMap::new();
+        for descriptor in &archive.chunk_descriptors {
+            *chunks_per_compression
+                .entry(archive.chunk_compression_of(descriptor).to_string())
+                .or_insert(0) += 1;
+        }
+        info!(
+            "  Chunks per compression: {}",
+            chunks_per_compression
+                .iter()
+                .map(|(compression, count)| format!("{}: {}", compression, count))
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    }
+    if let Some(ref dictionary) = archive.zstd_dictionary {
+        info!(
+            "  Zstd dictionary size: {}",
+            size_to_str(dictionary.data().len())
+        );
+    }
     info!("  Chunk hash length: {} bytes", archive.hash_length);
-    info!(
-        "  Hash window size: {}",
-        size_to_str(archive.chunker_params.buzhash_window_size)
-    );
+    if archive.chunker_params.algorithm == ChunkingAlgorithm::BuzHash {
+        info!(
+            "  Hash window size: {}",
+            size_to_str(archive.chunker_params.buzhash_window_size)
+        );
+    }
     info!(
         "  Archive size: {}",
         size_to_str(archive.compressed_size() + archive.header_size as u64)
@@ -46,6 +101,9 @@ pub fn print_archive(archive: &ArchiveReader) {
         "  Header checksum: {}",
         HexSlice::new(&archive.header_checksum)
     );
+    if let Some(ref signature) = archive.header_signature {
+        info!("  Header signature (Ed25519): {}", HexSlice::new(signature));
+    }
 
     info!("Source:");
     info!(
@@ -72,14 +130,7 @@ pub fn print_archive(archive: &ArchiveReader) {
 }
 
 pub fn run(config: &config::InfoConfig) -> Result<()> {
-    if &config.input[0..7] == "http://" || &config.input[0..8] == "https://" {
-        let remote_source = RemoteReader::new(&config.input);
-        print_archive_backend(remote_source)?;
-    } else {
-        let local_file =
-            File::open(&config.input).chain_err(|| format!("unable to open {}", config.input))?;
-        print_archive_backend(local_file)?;
-    }
+    let registry = BackendRegistry::with_http(config.http.clone());
 
-    Ok(())
+    print_archive_backend(registry.open(&config.input)?)
 }
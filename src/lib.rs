@@ -2,22 +2,37 @@
 extern crate error_chain;
 extern crate atty;
 extern crate blake2;
+extern crate brotli;
 extern crate crossbeam_channel;
+extern crate crossbeam_utils;
 extern crate curl;
+extern crate ed25519_dalek;
+extern crate lz4;
 extern crate lzma;
+extern crate memmap;
 extern crate protobuf;
 extern crate threadpool;
 extern crate zstd;
+extern crate zstd_safe;
 
 pub mod archive;
 pub mod archive_reader;
+pub mod backend_registry;
 pub mod buzhash;
+pub mod chunk_cache;
 pub mod chunk_dictionary;
 pub mod chunker;
 pub mod chunker_utils;
+pub mod clone_journal;
 pub mod compression;
 pub mod errors;
+pub mod fastcdc;
 pub mod file_archive_backend;
+pub mod in_place;
+pub mod mmap_archive_backend;
 pub mod para_pipe;
+pub mod parallel_chunker;
 pub mod remote_archive_backend;
+pub mod signing;
+pub mod stream_archive_backend;
 pub mod string_utils;
@@ -11,19 +11,23 @@ extern crate threadpool;
 mod clone_cmd;
 mod compress_cmd;
 mod config;
+mod diff_cmd;
 mod info_cmd;
 mod string_utils;
 
-use clap::{App, Arg, SubCommand};
+use clap::{App, Arg, ArgGroup, SubCommand};
 use log::*;
 use std::path::Path;
 use std::process;
+use std::time::Duration;
 use threadpool::ThreadPool;
 
 use crate::config::*;
 use crate::string_utils::hex_str_to_vec;
+use bita::chunker::ChunkingAlgorithm;
 use bita::compression::Compression;
 use bita::errors::*;
+use bita::remote_archive_backend::RemoteOptions;
 
 pub const PKG_NAME: &str = env!("CARGO_PKG_NAME");
 pub const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -44,6 +48,91 @@ fn parse_size(size_str: &str) -> usize {
     }
 }
 
+// Parse a compression given as TYPE[:LEVEL]
+fn parse_compression(compression_str: &str, default_level: u32) -> Result<Compression> {
+    let mut parts = compression_str.splitn(2, ':');
+    let compression_type = parts.next().unwrap_or("");
+    let level = match parts.next() {
+        Some(level) => level
+            .parse()
+            .chain_err(|| "invalid compression level value")?,
+        None => default_level,
+    };
+    let (compression, max_level) = match compression_type {
+        "LZMA" | "lzma" => (Compression::LZMA(level), 19),
+        "ZSTD" | "zstd" => (Compression::ZSTD(level), 19),
+        "LZ4" | "lz4" => (Compression::LZ4(level), 16),
+        "BROTLI" | "brotli" => (Compression::Brotli(level), 11),
+        "NONE" | "none" => (Compression::None, 19),
+        _ => bail!("invalid compression"),
+    };
+    if level < 1 || level > max_level {
+        bail!("compression level not within range");
+    }
+    Ok(compression)
+}
+
+// Arguments for setting up remote archive backends
+fn http_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("http-retry-count")
+            .long("http-retry-count")
+            .value_name("COUNT")
+            .help("Retry failed HTTP requests COUNT times [default: 0]"),
+        Arg::with_name("http-timeout")
+            .long("http-timeout")
+            .value_name("SECONDS")
+            .help("Fail a HTTP request if no data has been received for SECONDS"),
+        Arg::with_name("http-max-ranges")
+            .long("http-max-ranges")
+            .value_name("COUNT")
+            .help("Max number of ranges to fetch in a single HTTP request [default: 16]"),
+        Arg::with_name("http-concurrency")
+            .long("http-concurrency")
+            .value_name("COUNT")
+            .help("Max number of HTTP requests in flight at the same time [default: 1]"),
+        Arg::with_name("http-gap")
+            .long("http-gap")
+            .value_name("SIZE")
+            .help("Fetch chunks separated by at most SIZE unused bytes in a single HTTP range [default: chosen from measured latency and throughput]"),
+    ]
+}
+
+fn parse_http_options(matches: &clap::ArgMatches) -> Result<RemoteOptions> {
+    let retries = matches
+        .value_of("http-retry-count")
+        .unwrap_or("0")
+        .parse()
+        .chain_err(|| "invalid http retry count value")?;
+    let timeout = match matches.value_of("http-timeout") {
+        Some(timeout) => Some(Duration::from_secs(
+            timeout.parse().chain_err(|| "invalid http timeout value")?,
+        )),
+        None => None,
+    };
+    Ok(RemoteOptions {
+        retries,
+        timeout,
+        max_ranges: matches
+            .value_of("http-max-ranges")
+            .unwrap_or("16")
+            .parse()
+            .chain_err(|| "invalid http max ranges value")?,
+        max_concurrent: matches
+            .value_of("http-concurrency")
+            .unwrap_or("1")
+            .parse()
+            .chain_err(|| "invalid http concurrency value")?,
+        max_gap: matches.value_of("http-gap").map(|s| parse_size(s) as u64),
+    })
+}
+
+// A seed is used as a glob pattern if it holds any pattern characters and is
+// not the path of an existing file.
+fn is_glob_pattern(seed: &str) -> bool {
+    seed.contains(&['*', '?', '['][..]) && !Path::new(seed).exists()
+}
+
 fn init_log(level: log::LevelFilter) -> Result<()> {
     let local_level = level;
     fern::Dispatch::new()
@@ -119,6 +208,24 @@ fn parse_opts() -> Result<Config> {
                         .value_name("SIZE")
                         .help("Set size of the buzhash window [default: 16B]"),
                 )
+                .arg(
+                    Arg::with_name("fixed-size")
+                        .long("fixed-size")
+                        .value_name("SIZE")
+                        .help("Use fixed size chunks of SIZE instead of content defined chunk boundaries")
+                        .conflicts_with_all(&["avg-chunk-size", "min-chunk-size", "max-chunk-size", "buzhash-window"]),
+                )
+                .arg(
+                    Arg::with_name("parallel-chunking")
+                        .long("parallel-chunking")
+                        .help("Split the input file into regions which are chunked in parallel (the input is read twice)"),
+                )
+                .arg(
+                    Arg::with_name("fastcdc")
+                        .long("fastcdc")
+                        .help("Use FastCDC to find content defined chunk boundaries instead of buzhash")
+                        .conflicts_with_all(&["fixed-size", "buzhash-window"]),
+                )
                 .arg(
                     Arg::with_name("hash-length")
                         .long("hash-length")
@@ -135,7 +242,25 @@ fn parse_opts() -> Result<Config> {
                     Arg::with_name("compression")
                         .long("compression")
                         .value_name("TYPE")
-                        .help("Set the chunk data compression type (LZMA, ZSTD, NONE) [default: LZMA]"),
+                        .help("Set the chunk data compression type (LZMA, ZSTD, LZ4, BROTLI, NONE). Several types (with optional level) may be given, like ZSTD:19,LZMA:9, then each chunk is stored using the one giving the smallest result [default: LZMA]"),
+                )
+                .arg(
+                    Arg::with_name("zstd-dictionary")
+                        .long("zstd-dictionary")
+                        .value_name("SIZE")
+                        .help("Train a zstd dictionary of SIZE on a sample of the input chunks and use it for compressing all zstd chunks (requires an input file)"),
+                )
+                .arg(
+                    Arg::with_name("sign-key")
+                        .long("sign-key")
+                        .value_name("FILE")
+                        .help("Sign the archive header using the Ed25519 private key in FILE (raw or DER)"),
+                )
+                .arg(
+                    Arg::with_name("detached-header")
+                        .long("detached-header")
+                        .value_name("FILE")
+                        .help("Write the archive header to FILE and only chunk data to OUTPUT"),
                 )
                 .arg(
                     Arg::with_name("force-create")
@@ -150,7 +275,7 @@ fn parse_opts() -> Result<Config> {
                 .arg(
                     Arg::with_name("INPUT")
                         .value_name("INPUT")
-                        .help("Input file (can be a local archive or a URL)")
+                        .help("Input file (can be a local archive, a URL or - for stdin)")
                         .required(true),
                 )
                 .arg(
@@ -163,9 +288,82 @@ fn parse_opts() -> Result<Config> {
                     Arg::with_name("seed")
                         .value_name("FILE")
                         .long("seed")
-                        .help("File to use as seed while cloning or '-' to read from stdin")
+                        .help("File to use as seed while cloning, a glob pattern of files or '-' to read from stdin")
                         .multiple(true),
                 )
+                .arg(
+                    Arg::with_name("seed-dir")
+                        .value_name("DIR")
+                        .long("seed-dir")
+                        .help("Directory to search (recursively) for files to use as seed")
+                        .multiple(true),
+                )
+                .group(
+                    ArgGroup::with_name("seeds")
+                        .args(&["seed", "seed-dir"])
+                        .multiple(true),
+                )
+                .arg(
+                    Arg::with_name("parallel-seeds")
+                        .long("parallel-seeds")
+                        .help("Scan seed files concurrently, each in its own thread (useful when seeds are on separate storage)")
+                        .requires("seeds"),
+                )
+                .arg(
+                    Arg::with_name("parallel-chunking")
+                        .long("parallel-chunking")
+                        .help("Split seed files into regions which are chunked in parallel (seed files are read twice)")
+                        .requires("seeds"),
+                )
+                .arg(
+                    Arg::with_name("seed-archive-dict")
+                        .long("seed-archive-dict")
+                        .value_name("ARCHIVE")
+                        .help("Seed files are clones of ARCHIVE, read chunks using its dictionary instead of scanning")
+                        .requires("seeds"),
+                )
+                .arg(
+                    Arg::with_name("chunk-cache")
+                        .long("chunk-cache")
+                        .value_name("FILE")
+                        .help("Write a chunk cache describing the output to FILE, use it as seed on next clone to avoid scanning the output"),
+                )
+                .arg(
+                    Arg::with_name("skip-unchanged")
+                        .long("skip-unchanged")
+                        .help("Read output before writing and skip writing data which is already in place"),
+                )
+                .arg(
+                    Arg::with_name("in-place")
+                        .long("in-place")
+                        .help("Update an existing output in place, using its current content as seed")
+                        .conflicts_with("force-create"),
+                )
+                .arg(
+                    Arg::with_name("spill-dir")
+                        .long("spill-dir")
+                        .value_name("DIR")
+                        .help("Directory for temporarily storing chunks while moving them in place [default: directory of output, or the temp dir if output is a block device]")
+                        .requires("in-place"),
+                )
+                .arg(
+                    Arg::with_name("journal")
+                        .long("journal")
+                        .value_name("FILE")
+                        .help("Record progress of the clone in FILE, removed when the clone is complete")
+                        .conflicts_with("in-place"),
+                )
+                .arg(
+                    Arg::with_name("resume")
+                        .long("resume")
+                        .help("Resume an interrupted clone using the journal, only chunks not yet written are cloned")
+                        .requires("journal"),
+                )
+                .arg(
+                    Arg::with_name("mmap")
+                        .long("mmap")
+                        .help("Memory map a local input archive instead of reading it"),
+                )
                 .arg(
                     Arg::with_name("force-create")
                         .short("f")
@@ -176,7 +374,20 @@ fn parse_opts() -> Result<Config> {
                         .long("verify-header")
                         .value_name("CHECKSUM")
                         .help("Verify that the archive header checksum is the one given"),
-                ),
+                )
+                .arg(
+                    Arg::with_name("header")
+                        .long("header")
+                        .value_name("HEADER")
+                        .help("Read the archive header from HEADER (local file or URL), INPUT then only holds chunk data"),
+                )
+                .arg(
+                    Arg::with_name("verify-key")
+                        .long("verify-key")
+                        .value_name("FILE")
+                        .help("Verify that the archive header is signed by the Ed25519 public key in FILE (raw or DER)"),
+                )
+                .args(&http_args()),
         )
         .subcommand(
             SubCommand::with_name("info")
@@ -184,9 +395,27 @@ fn parse_opts() -> Result<Config> {
                 .arg(
                     Arg::with_name("INPUT")
                         .value_name("INPUT")
-                        .help("Input file (can be a local archive or a URL)")
+                        .help("Input file (can be a local archive, a URL or - for stdin)")
                         .required(true),
                 )
+                .args(&http_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Compare the chunks of two archives.")
+                .arg(
+                    Arg::with_name("FROM")
+                        .value_name("FROM")
+                        .help("Archive already held by the client (can be a local archive or a URL)")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("TO")
+                        .value_name("TO")
+                        .help("Archive to update to (can be a local archive or a URL)")
+                        .required(true),
+                )
+                .args(&http_args()),
         )
         .get_matches();
 
@@ -207,9 +436,9 @@ fn parse_opts() -> Result<Config> {
         let temp_file = Path::with_extension(output, ".tmp");
 
         let avg_chunk_size = parse_size(matches.value_of("avg-chunk-size").unwrap_or("64KiB"));
-        let min_chunk_size = parse_size(matches.value_of("min-chunk-size").unwrap_or("16KiB"));
-        let max_chunk_size = parse_size(matches.value_of("max-chunk-size").unwrap_or("16MiB"));
-        let hash_window_size = parse_size(matches.value_of("buzhash-window").unwrap_or("16B"));
+        let mut min_chunk_size = parse_size(matches.value_of("min-chunk-size").unwrap_or("16KiB"));
+        let mut max_chunk_size = parse_size(matches.value_of("max-chunk-size").unwrap_or("16MiB"));
+        let mut hash_window_size = parse_size(matches.value_of("buzhash-window").unwrap_or("16B"));
         let hash_length = matches.value_of("hash-length").unwrap_or("64");
 
         let compression_level = matches
@@ -222,21 +451,60 @@ fn parse_opts() -> Result<Config> {
             bail!("compression level not within range");
         }
 
-        let compression = match matches.value_of("compression").unwrap_or("LZMA") {
-            "LZMA" | "lzma" => Compression::LZMA(compression_level),
-            "ZSTD" | "zstd" => Compression::ZSTD(compression_level),
-            "NONE" | "none" => Compression::None,
-            _ => bail!("invalid compression"),
-        };
+        let compressions = matches
+            .value_of("compression")
+            .unwrap_or("LZMA")
+            .split(',')
+            .map(|compression| parse_compression(compression, compression_level))
+            .collect::<Result<Vec<Compression>>>()?;
 
-        let chunk_filter_bits = 30 - (avg_chunk_size as u32).leading_zeros();
-        if min_chunk_size > avg_chunk_size {
-            bail!("min-chunk-size > avg-chunk-size");
+        if matches.is_present("parallel-chunking") && input.is_none() {
+            bail!("parallel-chunking requires an input file, stdin can not be split into regions");
         }
-        if max_chunk_size < avg_chunk_size {
-            bail!("max-chunk-size < avg-chunk-size");
+        let zstd_dictionary_size = matches.value_of("zstd-dictionary").map(parse_size);
+        if let Some(size) = zstd_dictionary_size {
+            if size == 0 {
+                bail!("zstd-dictionary must be greater than 0");
+            }
+            if input.is_none() {
+                bail!("zstd-dictionary requires an input file");
+            }
+            if !compressions.iter().any(|compression| match compression {
+                Compression::ZSTD(_) => true,
+                _ => false,
+            }) {
+                bail!("zstd-dictionary requires ZSTD compression");
+            }
         }
 
+        let mut chunk_filter_bits = 30 - (avg_chunk_size as u32).leading_zeros();
+        let chunking_algorithm = if let Some(fixed_size) = matches.value_of("fixed-size") {
+            let fixed_size = parse_size(fixed_size);
+            if fixed_size == 0 {
+                bail!("fixed-size must be greater than 0");
+            }
+            // Fixed size chunks are described by min and max chunk size only
+            chunk_filter_bits = 0;
+            min_chunk_size = fixed_size;
+            max_chunk_size = fixed_size;
+            hash_window_size = 0;
+            ChunkingAlgorithm::FixedSize
+        } else {
+            if min_chunk_size > avg_chunk_size {
+                bail!("min-chunk-size > avg-chunk-size");
+            }
+            if max_chunk_size < avg_chunk_size {
+                bail!("max-chunk-size < avg-chunk-size");
+            }
+            if matches.is_present("fastcdc") {
+                // The gear hash used by FastCDC has no configurable window
+                hash_window_size = 0;
+                ChunkingAlgorithm::FastCdc
+            } else {
+                ChunkingAlgorithm::BuzHash
+            }
+        };
+
         Ok(Config::Compress(CompressConfig {
             input,
             output: output.to_path_buf(),
@@ -245,47 +513,106 @@ fn parse_opts() -> Result<Config> {
                 .chain_err(|| "invalid hash length value")?,
             force_create: matches.is_present("force-create"),
             temp_file,
+            chunking_algorithm,
             chunk_filter_bits,
             min_chunk_size,
             max_chunk_size,
             hash_window_size,
             compression_level,
-            compression,
+            compressions,
+            zstd_dictionary_size,
+            parallel_chunking: matches.is_present("parallel-chunking"),
+            sign_key: matches
+                .value_of("sign-key")
+                .map(|s| Path::new(s).to_path_buf()),
+            detached_header: matches
+                .value_of("detached-header")
+                .map(|s| Path::new(s).to_path_buf()),
         }))
     } else if let Some(matches) = matches.subcommand_matches("clone") {
         let input = matches.value_of("INPUT").unwrap();
         let output = matches.value_of("OUTPUT").unwrap_or("");
         let mut seed_stdin = false;
-        let seed_files = matches
-            .values_of("seed")
+        let mut seed_files = Vec::new();
+        let mut seed_patterns = Vec::new();
+        for seed in matches.values_of("seed").unwrap_or_default() {
+            if seed == "-" {
+                seed_stdin = true;
+            } else if is_glob_pattern(seed) {
+                seed_patterns.push(seed.to_string());
+            } else {
+                seed_files.push(Path::new(seed).to_path_buf());
+            }
+        }
+        let seed_dirs = matches
+            .values_of("seed-dir")
             .unwrap_or_default()
-            .filter(|s| {
-                if *s == "-" {
-                    seed_stdin = true;
-                    false
-                } else {
-                    true
-                }
-            })
             .map(|s| Path::new(s).to_path_buf())
             .collect();
+        let header = matches.value_of("header");
+        if [input == "-", header == Some("-"), seed_stdin]
+            .iter()
+            .filter(|uses_stdin| **uses_stdin)
+            .count()
+            > 1
+        {
+            bail!("stdin can only be used as one of input archive, header and seed");
+        }
+        if matches.is_present("parallel-chunking")
+            && seed_files.is_empty()
+            && seed_patterns.is_empty()
+            && !matches.is_present("seed-dir")
+        {
+            bail!("parallel-chunking requires seed files, stdin can not be split into regions");
+        }
 
+        let http = parse_http_options(matches)?;
         let verify_header = matches
             .value_of("verify-header")
             .map(|c| hex_str_to_vec(c).expect("failed to parse checksum"));
 
         Ok(Config::Clone(CloneConfig {
             input: input.to_string(),
+            header: header.map(|s| s.to_string()),
             output: Path::new(output).to_path_buf(),
             force_create: matches.is_present("force-create"),
             header_checksum: verify_header,
+            verify_key: matches
+                .value_of("verify-key")
+                .map(|s| Path::new(s).to_path_buf()),
             seed_files,
+            seed_patterns,
+            seed_dirs,
             seed_stdin,
+            parallel_seeds: matches.is_present("parallel-seeds"),
+            parallel_chunking: matches.is_present("parallel-chunking"),
+            seed_archive_dict: matches.value_of("seed-archive-dict").map(|s| s.to_string()),
+            chunk_cache: matches
+                .value_of("chunk-cache")
+                .map(|s| Path::new(s).to_path_buf()),
+            skip_unchanged: matches.is_present("skip-unchanged"),
+            in_place: matches.is_present("in-place"),
+            spill_dir: matches
+                .value_of("spill-dir")
+                .map(|s| Path::new(s).to_path_buf()),
+            journal: matches
+                .value_of("journal")
+                .map(|s| Path::new(s).to_path_buf()),
+            resume: matches.is_present("resume"),
+            http,
+            mmap: matches.is_present("mmap"),
         }))
     } else if let Some(matches) = matches.subcommand_matches("info") {
         let input = matches.value_of("INPUT").unwrap();
         Ok(Config::Info(InfoConfig {
             input: input.to_string(),
+            http: parse_http_options(matches)?,
+        }))
+    } else if let Some(matches) = matches.subcommand_matches("diff") {
+        Ok(Config::Diff(DiffConfig {
+            from: matches.value_of("FROM").unwrap().to_string(),
+            to: matches.value_of("TO").unwrap().to_string(),
+            http: parse_http_options(matches)?,
         }))
     } else {
         error!("Unknown command");
@@ -301,6 +628,7 @@ fn main() {
         Ok(Config::Compress(config)) => compress_cmd::run(&config, &pool),
         Ok(Config::Clone(config)) => clone_cmd::run(&config, &pool),
         Ok(Config::Info(config)) => info_cmd::run(&config),
+        Ok(Config::Diff(config)) => diff_cmd::run(&config),
         Err(e) => Err(e),
     };
     if let Err(ref e) = result {
@@ -0,0 +1,143 @@
+use memmap::Mmap;
+use std::cmp;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::archive_reader::{ArchiveBackend, SharedArchiveData};
+use crate::errors::*;
+
+// Archive backend reading a local archive through a read-only memory map,
+// which avoids a seek and read syscall for every chunk read. The mapping is
+// shared with the threads decompressing chunks, so chunk data is never copied.
+pub struct MmapReader {
+    mmap: Arc<Mmap>,
+    read_offset: u64,
+}
+
+impl MmapReader {
+    pub fn new(file: &File) -> Result<Self> {
+        // The archive must not be modified while mapped
+        let mmap = unsafe { Mmap::map(file) }.chain_err(|| "failed to map archive file")?;
+        Ok(MmapReader {
+            mmap: Arc::new(mmap),
+            read_offset: 0,
+        })
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).chain_err(|| format!("unable to open {}", path.display()))?;
+        Self::new(&file)
+    }
+
+    // Get the data at offset, fails if not all of it is present in the archive
+    fn slice(&self, offset: u64, size: u64) -> Result<&[u8]> {
+        let end = match offset.checked_add(size) {
+            Some(end) if end <= self.mmap.len() as u64 => end,
+            _ => bail!("failed to read archive file (read beyond end of file)"),
+        };
+        Ok(&self.mmap[offset as usize..end as usize])
+    }
+}
+
+impl io::Read for MmapReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::result::Result<usize, io::Error> {
+        let offset = cmp::min(self.read_offset, self.mmap.len() as u64) as usize;
+        let size = cmp::min(buf.len(), self.mmap.len() - offset);
+        buf[..size].copy_from_slice(&self.mmap[offset..offset + size]);
+        self.read_offset += size as u64;
+        Ok(size)
+    }
+}
+
+impl ArchiveBackend for MmapReader {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        buf.copy_from_slice(self.slice(offset, buf.len() as u64)?);
+        Ok(())
+    }
+
+    fn read_in_chunks(
+        &mut self,
+        start_offset: u64,
+        chunk_sizes: &[u64],
+        chunk_callback: &mut dyn FnMut(&[u8]) -> Result<()>,
+    ) -> Result<()> {
+        let mut offset = start_offset;
+        for chunk_size in chunk_sizes {
+            chunk_callback(self.slice(offset, *chunk_size)?)?;
+            offset += chunk_size;
+        }
+        Ok(())
+    }
+
+    fn shared_data(&self) -> Option<SharedArchiveData> {
+        Some(self.mmap.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+    use std::path::PathBuf;
+    use std::process;
+
+    // Map a temporary file with the given content
+    fn mmap_reader(name: &str, data: &[u8]) -> (PathBuf, MmapReader) {
+        let path = env::temp_dir().join(format!("bita-mmap-{}-{}", process::id(), name));
+        fs::write(&path, data).unwrap();
+        let reader = MmapReader::open(&path).unwrap();
+        (path, reader)
+    }
+
+    #[test]
+    fn read_at_within_file() {
+        let data: Vec<u8> = (0..100).collect();
+        let (path, mut reader) = mmap_reader("read-at", &data);
+        let mut buf = vec![0; 10];
+        reader.read_at(20, &mut buf).unwrap();
+        assert_eq!(&buf[..], &data[20..30]);
+        // Up to the last byte of file
+        reader.read_at(90, &mut buf).unwrap();
+        assert_eq!(&buf[..], &data[90..100]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_past_end_of_file() {
+        let data: Vec<u8> = (0..100).collect();
+        let (path, mut reader) = mmap_reader("past-eof", &data);
+        let mut buf = vec![0; 10];
+        assert!(reader.read_at(91, &mut buf).is_err());
+        assert!(reader.read_at(200, &mut buf).is_err());
+        assert!(reader.read_at(u64::max_value() - 5, &mut buf).is_err());
+        let mut chunks = Vec::new();
+        assert!(reader
+            .read_in_chunks(80, &[10, 10, 10], &mut |chunk: &[u8]| {
+                chunks.push(chunk.to_vec());
+                Ok(())
+            })
+            .is_err());
+        // Chunks before the end of file are still forwarded
+        assert_eq!(chunks, vec![data[80..90].to_vec(), data[90..100].to_vec()]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn shared_data_is_whole_file() {
+        let data: Vec<u8> = (0..100).collect();
+        let (path, mut reader) = mmap_reader("shared", &data);
+        let shared = reader.shared_data().unwrap();
+        assert_eq!((*shared).as_ref(), &data[..]);
+        // Sequential reads stop at end of file
+        let mut buf = vec![0; 60];
+        assert_eq!(reader.read(&mut buf).unwrap(), 60);
+        assert_eq!(reader.read(&mut buf).unwrap(), 40);
+        assert_eq!(&buf[..40], &data[60..]);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,198 @@
+use crossbeam_utils::thread;
+use std::cmp;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+use crate::chunker::{Chunker, ChunkerParams};
+
+// Find the chunk boundaries of a seekable source using multiple threads.
+//
+// The source is split into regions which are chunked in parallel, each as if
+// there was a chunk boundary at the start of the region. Since the chunker
+// state after a boundary only depends on the boundary offset and the data
+// around it, the chunking of a region is in sync with the sequential chunking
+// from the first boundary which both agree on. The regions are then stitched
+// together by continuing the chunker of the previous region until it hits a
+// boundary of the next region.
+//
+// Returns the end offset of every chunk, identical to chunking the source
+// sequentially. Each region is read through its own source, given by
+// open_source.
+pub fn find_chunk_boundaries<R, F>(
+    params: &ChunkerParams,
+    num_regions: usize,
+    open_source: F,
+) -> io::Result<Vec<u64>>
+where
+    R: Read + Seek + Send,
+    F: Fn() -> io::Result<R>,
+{
+    let mut source = open_source()?;
+    let source_size = source.seek(SeekFrom::End(0))?;
+
+    // Regions are aligned to the max chunk size, which keeps fixed size chunking
+    // in sync from the start of every region.
+    let align = cmp::max(params.max_chunk_size as u64, 1);
+    let region_size = source_size / cmp::max(num_regions as u64, 1) + 1;
+    let region_size = (region_size + align - 1) / align * align;
+
+    let mut sources = Vec::new();
+    let mut region_start = 0;
+    loop {
+        source.seek(SeekFrom::Start(region_start))?;
+        let region_end = cmp::min(region_start + region_size, source_size);
+        sources.push((region_start, region_end, source));
+        region_start = region_end;
+        if region_start >= source_size {
+            break;
+        }
+        source = open_source()?;
+    }
+
+    let regions = thread::scope(|scope| {
+        let region_threads: Vec<_> = sources
+            .iter_mut()
+            .map(|(region_start, region_end, source)| {
+                let (region_start, region_end) = (*region_start, *region_end);
+                scope.spawn(move |_| -> io::Result<_> {
+                    let mut chunker = Chunker::new(params.clone(), source);
+                    let boundaries = chunk_region(&mut chunker, region_start, region_end)?;
+                    Ok((region_start, chunker, boundaries))
+                })
+            })
+            .collect();
+        region_threads
+            .into_iter()
+            .map(|region_thread| region_thread.join().expect("chunker thread"))
+            .collect::<io::Result<Vec<_>>>()
+    })
+    .expect("chunker threads")?;
+
+    // The first region starts at the source start and is in sync from the beginning
+    let mut regions = regions.into_iter();
+    let (mut chunker_start, mut chunker, mut boundaries) = regions.next().expect("first region");
+    for (region_start, region_chunker, region_boundaries) in regions {
+        // Continue the chunker in sync until it finds a boundary which is also
+        // a boundary of the next region. From there the next region is in sync.
+        loop {
+            let last_boundary = *boundaries.last().unwrap_or(&0);
+            if last_boundary >= region_start
+                && region_boundaries.binary_search(&last_boundary).is_ok()
+            {
+                boundaries.extend(region_boundaries.iter().filter(|b| **b > last_boundary));
+                chunker = region_chunker;
+                chunker_start = region_start;
+                break;
+            }
+            if last_boundary >= *region_boundaries.last().unwrap_or(&0) {
+                // Never in sync with the region, keep on using the same chunker
+                break;
+            }
+            match chunker.scan()? {
+                Some((offset, data)) => boundaries.push(chunker_start + offset + data.len() as u64),
+                None => break,
+            }
+        }
+    }
+
+    // Chunk the rest of the source, if the last region was never in sync
+    while let Some((offset, data)) = chunker.scan()? {
+        boundaries.push(chunker_start + offset + data.len() as u64);
+    }
+    Ok(boundaries)
+}
+
+// Chunk a region, stopping at the first chunk boundary at or past the region end.
+// Returns the end offset of every chunk found.
+fn chunk_region<R: Read>(
+    chunker: &mut Chunker<R>,
+    region_start: u64,
+    region_end: u64,
+) -> io::Result<Vec<u64>> {
+    let mut boundaries = Vec::new();
+    while let Some((offset, data)) = chunker.scan()? {
+        let chunk_end = region_start + offset + data.len() as u64;
+        boundaries.push(chunk_end);
+        if chunk_end >= region_end {
+            break;
+        }
+    }
+    Ok(boundaries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::BUZHASH_SEED;
+    use std::io::Cursor;
+
+    fn sequential_boundaries(params: &ChunkerParams, data: &[u8]) -> Vec<u64> {
+        let mut src = data;
+        let mut chunker = Chunker::new(params.clone(), &mut src);
+        let mut boundaries = Vec::new();
+        while let Some((offset, data)) = chunker.scan().unwrap() {
+            boundaries.push(offset + data.len() as u64);
+        }
+        boundaries
+    }
+
+    fn pseudo_random_data(size: usize) -> Vec<u8> {
+        let mut state: u32 = 0x1234_5678;
+        (0..size)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    fn assert_same_boundaries(params: ChunkerParams, data: &[u8]) {
+        let expected = sequential_boundaries(&params, data);
+        for num_regions in &[1, 2, 3, 7, 16] {
+            let boundaries =
+                find_chunk_boundaries(&params, *num_regions, || Ok(Cursor::new(data))).unwrap();
+            assert_eq!(boundaries, expected, "regions: {}", num_regions);
+        }
+    }
+
+    #[test]
+    fn same_as_sequential() {
+        let mut data = pseudo_random_data(200_000);
+        // Include a stretch of equal bytes where the rolling hash does not change
+        for b in &mut data[50_000..80_000] {
+            *b = 0;
+        }
+        assert_same_boundaries(ChunkerParams::new(8, 64, 4096, 32, BUZHASH_SEED), &data);
+        assert_same_boundaries(ChunkerParams::new(6, 0, 1000, 48, BUZHASH_SEED), &data);
+        assert_same_boundaries(ChunkerParams::new(6, 40, 800, 48, BUZHASH_SEED), &data);
+        assert_same_boundaries(ChunkerParams::new_fastcdc(8, 128, 4096), &data);
+        assert_same_boundaries(ChunkerParams::new_fixed_size(3000), &data);
+    }
+
+    #[test]
+    fn small_source() {
+        let params = ChunkerParams::new(8, 64, 4096, 32, BUZHASH_SEED);
+        assert_same_boundaries(params.clone(), &[]);
+        assert_same_boundaries(params, &pseudo_random_data(100));
+    }
+
+    #[test]
+    fn chunker_with_boundaries() {
+        let data = pseudo_random_data(10_000);
+        let params = ChunkerParams::new_fastcdc(8, 128, 4096);
+        let boundaries = sequential_boundaries(&params, &data);
+        let mut src = &data[..];
+        let mut chunker = Chunker::with_boundaries(params.clone(), &mut src, boundaries.clone());
+        let mut chunk_start = 0;
+        for chunk_end in boundaries {
+            let (offset, chunk_data) = chunker.scan().unwrap().unwrap();
+            assert_eq!(offset, chunk_start);
+            assert_eq!(chunk_data, &data[chunk_start as usize..chunk_end as usize]);
+            chunk_start = chunk_end;
+        }
+        assert!(chunker.scan().unwrap().is_none());
+    }
+}
@@ -1,13 +1,458 @@
-use curl::easy::Easy;
+use curl::easy::{Easy, Easy2, Handler, WriteError};
+use curl::multi::{Easy2Handle, Multi};
+use log::*;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
 use std::io;
+use std::mem;
+use std::thread;
+use std::time::Duration;
 
 use crate::archive_reader::ArchiveBackend;
 use crate::errors::*;
 
+// Options for remote readers, shared by all readers created through a backend
+// registry.
+#[derive(Clone, Debug)]
+pub struct RemoteOptions {
+    // Number of times to retry a failed request
+    pub retries: u32,
+
+    // Fail a request if no data has been received within this time
+    pub timeout: Option<Duration>,
+
+    // Max number of chunk groups to request in a single multi-range request
+    pub max_ranges: usize,
+
+    // Max number of requests in flight at the same time
+    pub max_concurrent: usize,
+
+    // Fixed max gap size, chosen from the transfer stats if not given
+    pub max_gap: Option<u64>,
+}
+
+impl Default for RemoteOptions {
+    fn default() -> Self {
+        RemoteOptions {
+            retries: 0,
+            timeout: None,
+            max_ranges: 1,
+            max_concurrent: 1,
+            max_gap: None,
+        }
+    }
+}
+
 pub struct RemoteReader {
     url: String,
     handle: curl::easy::Easy,
     read_offset: u64,
+
+    // Number of times to retry a failed request
+    retries: u32,
+
+    // Delay before first retry, doubled for each following retry
+    retry_delay: Duration,
+
+    // Fail a request if no data has been received within this time
+    timeout: Option<Duration>,
+
+    // Max number of chunk groups to request in a single multi-range request
+    max_ranges: usize,
+
+    // Cleared if the server is found to not respond to multi-range requests
+    multi_range_supported: bool,
+
+    // Max number of requests to have in flight at the same time
+    max_concurrent: usize,
+
+    // Max size of gap between chunks to read and discard instead of making
+    // a new request. Chosen from the measured transfer stats if not set.
+    max_gap: Option<u64>,
+
+    transfer_stats: TransferStats,
+}
+
+// Max total size of the chunk groups fetched in a multi-range request, or in
+// a concurrent request. The response is buffered before chunks are forwarded.
+const MULTI_RANGE_MAX_SIZE: u64 = 16 * 1024 * 1024;
+
+// Throughput assumed until a transfer large enough to measure it is made
+const ASSUMED_THROUGHPUT: f64 = 1024.0 * 1024.0;
+
+// Smallest transfer used to measure throughput
+const THROUGHPUT_SAMPLE_MIN_SIZE: f64 = 64.0 * 1024.0;
+
+// Max size of gap chosen from the transfer stats
+const MAX_AUTO_GAP: u64 = 1024 * 1024;
+
+fn duration_to_secs(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1_000_000.0
+}
+
+// Measured latency (seconds) and throughput (bytes per second) of the
+// transfers made, as a moving average.
+#[derive(Default)]
+struct TransferStats {
+    latency: Option<f64>,
+    throughput: Option<f64>,
+}
+
+impl TransferStats {
+    fn average(current: Option<f64>, sample: f64) -> Option<f64> {
+        Some(match current {
+            Some(current) => current * 0.75 + sample * 0.25,
+            None => sample,
+        })
+    }
+
+    // Add a sample from the timings of a finished transfer
+    fn add_sample(
+        &mut self,
+        pretransfer_time: Duration,
+        starttransfer_time: Duration,
+        total_time: Duration,
+        size: f64,
+    ) {
+        if let Some(latency) = starttransfer_time.checked_sub(pretransfer_time) {
+            self.latency = Self::average(self.latency, duration_to_secs(latency));
+        }
+        if let Some(transfer_time) = total_time.checked_sub(starttransfer_time) {
+            if size >= THROUGHPUT_SAMPLE_MIN_SIZE && transfer_time >= Duration::from_millis(1) {
+                self.throughput =
+                    Self::average(self.throughput, size / duration_to_secs(transfer_time));
+            }
+        }
+    }
+
+    // A gap is worth reading if that takes less time than waiting for the
+    // response of a new request.
+    fn gap_size(&self) -> u64 {
+        let latency = self.latency.unwrap_or(0.0);
+        let throughput = self.throughput.unwrap_or(ASSUMED_THROUGHPUT);
+        cmp::min((latency * throughput) as u64, MAX_AUTO_GAP)
+    }
+}
+
+// Headers of interest in a range response
+#[derive(Default)]
+struct ResponseHeaders {
+    status: u32,
+    content_type: String,
+    content_range: String,
+    ranges_ignored: bool,
+}
+
+impl ResponseHeaders {
+    // Handle a received header line. Returns false if the transfer should be
+    // aborted.
+    fn header_line(&mut self, header: &[u8]) -> bool {
+        let header = String::from_utf8_lossy(header);
+        let header = header.trim();
+        if header.starts_with("HTTP/") {
+            // A new response (there might be several if redirected)
+            *self = ResponseHeaders::default();
+            self.status = header
+                .split_whitespace()
+                .nth(1)
+                .and_then(|code| code.parse().ok())
+                .unwrap_or(0);
+        } else if header.is_empty() && self.status == 200 {
+            // The server ignored the range request and is about to send the
+            // whole file, abort.
+            self.ranges_ignored = true;
+            return false;
+        } else {
+            let mut kv = header.splitn(2, ':');
+            if let (Some(name), Some(value)) = (kv.next(), kv.next()) {
+                match name.trim().to_lowercase().as_str() {
+                    "content-type" => self.content_type = value.trim().to_string(),
+                    "content-range" => self.content_range = value.trim().to_string(),
+                    _ => (),
+                }
+            }
+        }
+        true
+    }
+
+    // Get the ranges of a response body to a request of the given groups.
+    // Returns None if the server did not respond with the requested ranges.
+    fn into_parts(
+        self,
+        body: Vec<u8>,
+        groups: &[(u64, Vec<u64>)],
+    ) -> Result<Option<Vec<RangePart>>> {
+        let requested = requested_span(groups);
+        if self.ranges_ignored {
+            Ok(None)
+        } else if let Some(boundary) = multipart_boundary(&self.content_type) {
+            Ok(Some(parse_multipart_byteranges(
+                &body, &boundary, requested,
+            )?))
+        } else if !self.content_range.is_empty() {
+            // The server responded with a single range, probably since only
+            // a single range was requested or the requested ranges were merged.
+            let (start, end) = parse_content_range(&self.content_range)?;
+            check_content_range(start, end, requested)?;
+            Ok(Some(vec![RangePart {
+                offset: start,
+                data: body,
+            }]))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+// Collects a range response of a concurrent request
+#[derive(Default)]
+struct RangeResponse {
+    headers: ResponseHeaders,
+    body: Vec<u8>,
+}
+
+impl Handler for RangeResponse {
+    fn write(&mut self, data: &[u8]) -> std::result::Result<usize, WriteError> {
+        self.body.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn header(&mut self, data: &[u8]) -> bool {
+        self.headers.header_line(data)
+    }
+}
+
+// Range header value for requesting the given groups of chunks
+fn range_of_groups(groups: &[(u64, Vec<u64>)]) -> String {
+    groups
+        .iter()
+        .map(|(start_offset, chunk_sizes)| {
+            let size: u64 = chunk_sizes.iter().sum();
+            format!("{}-{}", start_offset, start_offset + size - 1)
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+// First and last byte of the range spanning all the given groups of chunks
+fn requested_span(groups: &[(u64, Vec<u64>)]) -> (u64, u64) {
+    let start = groups.first().map_or(0, |(start_offset, _)| *start_offset);
+    let end = groups.last().map_or(0, |(start_offset, chunk_sizes)| {
+        start_offset + chunk_sizes.iter().sum::<u64>() - 1
+    });
+    (start, end)
+}
+
+// Split groups of chunks into groups of at most max_size (unless a single
+// chunk is larger).
+fn split_groups(groups: &[(u64, Vec<u64>)], max_size: u64) -> Vec<(u64, Vec<u64>)> {
+    let mut split = Vec::new();
+    for (start_offset, chunk_sizes) in groups {
+        let mut offset = *start_offset;
+        let mut group: (u64, Vec<u64>) = (offset, Vec::new());
+        let mut group_size = 0;
+        for chunk_size in chunk_sizes {
+            if !group.1.is_empty() && group_size + chunk_size > max_size {
+                split.push(group);
+                group = (offset, Vec::new());
+                group_size = 0;
+            }
+            group.1.push(*chunk_size);
+            group_size += chunk_size;
+            offset += chunk_size;
+        }
+        if !group.1.is_empty() {
+            split.push(group);
+        }
+    }
+    split
+}
+
+// Divide groups into batches to fetch in a single request each. A batch
+// holds at most max_ranges groups.
+fn batch_groups(groups: &[(u64, Vec<u64>)], max_ranges: usize) -> Vec<std::ops::Range<usize>> {
+    let mut batches = Vec::new();
+    let mut group_index = 0;
+    while group_index < groups.len() {
+        let mut batch_end = group_index + 1;
+        let mut batch_size: u64 = groups[group_index].1.iter().sum();
+        while batch_end < groups.len() && batch_end - group_index < max_ranges {
+            let group_size: u64 = groups[batch_end].1.iter().sum();
+            if batch_size + group_size > MULTI_RANGE_MAX_SIZE {
+                break;
+            }
+            batch_size += group_size;
+            batch_end += 1;
+        }
+        batches.push(group_index..batch_end);
+        group_index = batch_end;
+    }
+    batches
+}
+
+// Forward the chunks of each group from the response part containing it
+fn forward_parts(
+    groups: &[(u64, Vec<u64>)],
+    parts: &[RangePart],
+    chunk_callback: &mut dyn FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    for (start_offset, chunk_sizes) in groups {
+        let group_size: u64 = chunk_sizes.iter().sum();
+        let part = parts
+            .iter()
+            .find(|part| {
+                part.offset <= *start_offset
+                    && part.offset + part.data.len() as u64 >= start_offset + group_size
+            })
+            .chain_err(|| "requested range missing in response")?;
+        let mut offset = (start_offset - part.offset) as usize;
+        for chunk_size in chunk_sizes {
+            let chunk_end = offset + *chunk_size as usize;
+            chunk_callback(&part.data[offset..chunk_end])?;
+            offset = chunk_end;
+        }
+    }
+    Ok(())
+}
+
+// A part of a multi-range response, data starting at offset
+#[derive(Debug, PartialEq)]
+struct RangePart {
+    offset: u64,
+    data: Vec<u8>,
+}
+
+// Parse a range header value on the form 'bytes <start>-<end>/<total>'
+fn parse_content_range(value: &str) -> Result<(u64, u64)> {
+    let value = value.trim();
+    if !value.starts_with("bytes ") {
+        bail!("invalid content range ({})", value);
+    }
+    let range = value["bytes ".len()..].split('/').next().unwrap_or("");
+    let mut bounds = range.splitn(2, '-');
+    match (bounds.next(), bounds.next()) {
+        (Some(start), Some(end)) => {
+            let start: u64 = start
+                .trim()
+                .parse()
+                .chain_err(|| format!("invalid content range ({})", value))?;
+            let end: u64 = end
+                .trim()
+                .parse()
+                .chain_err(|| format!("invalid content range ({})", value))?;
+            if end < start {
+                bail!("invalid content range ({})", value);
+            }
+            Ok((start, end))
+        }
+        _ => bail!("invalid content range ({})", value),
+    }
+}
+
+// Check that a range responded with is inside of the requested range
+fn check_content_range(start: u64, end: u64, requested: (u64, u64)) -> Result<()> {
+    if start < requested.0 || end > requested.1 {
+        bail!(
+            "content range {}-{} outside of requested range {}-{}",
+            start,
+            end,
+            requested.0,
+            requested.1
+        );
+    }
+    Ok(())
+}
+
+// Get the boundary of a 'multipart/byteranges' content type
+fn multipart_boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    if params.next()?.trim().to_lowercase() != "multipart/byteranges" {
+        return None;
+    }
+    params
+        .filter_map(|param| {
+            let mut kv = param.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(key), Some(value)) if key.trim().to_lowercase() == "boundary" => {
+                    Some(value.trim().trim_matches('"').to_string())
+                }
+                _ => None,
+            }
+        })
+        .next()
+}
+
+fn find_subslice(data: &[u8], pattern: &[u8], from: usize) -> Option<usize> {
+    if from > data.len() {
+        return None;
+    }
+    data[from..]
+        .windows(pattern.len())
+        .position(|window| window == pattern)
+        .map(|pos| from + pos)
+}
+
+// Parse the body of a multipart/byteranges response, where every part must be
+// inside of the requested range (first and last byte).
+// The data size of each part is given by its content range, hence the data
+// itself is never searched for the boundary.
+fn parse_multipart_byteranges(
+    body: &[u8],
+    boundary: &str,
+    requested: (u64, u64),
+) -> Result<Vec<RangePart>> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut parts = Vec::new();
+    let mut pos = match find_subslice(body, &delimiter, 0) {
+        Some(pos) => pos + delimiter.len(),
+        None => bail!("no boundary found in multipart response"),
+    };
+    loop {
+        if body[pos..].starts_with(b"--") {
+            // Close delimiter
+            return Ok(parts);
+        }
+        // Skip the rest of the delimiter line and read the part headers
+        pos = match find_subslice(body, b"\r\n", pos) {
+            Some(pos) => pos + 2,
+            None => bail!("unexpected end of multipart response"),
+        };
+        let mut content_range = None;
+        loop {
+            let line_end = match find_subslice(body, b"\r\n", pos) {
+                Some(line_end) => line_end,
+                None => bail!("unexpected end of multipart response"),
+            };
+            let line = String::from_utf8_lossy(&body[pos..line_end]).to_string();
+            pos = line_end + 2;
+            if line.is_empty() {
+                break;
+            }
+            let mut header = line.splitn(2, ':');
+            if let (Some(name), Some(value)) = (header.next(), header.next()) {
+                if name.trim().to_lowercase() == "content-range" {
+                    content_range = Some(parse_content_range(value)?);
+                }
+            }
+        }
+        let (start, end) = match content_range {
+            Some(range) => range,
+            None => bail!("missing content range in multipart response"),
+        };
+        check_content_range(start, end, requested)?;
+        let data_end = pos + (end - start + 1) as usize;
+        if data_end > body.len() {
+            bail!("unexpected end of multipart response");
+        }
+        parts.push(RangePart {
+            offset: start,
+            data: body[pos..data_end].to_vec(),
+        });
+        pos = match find_subslice(body, &delimiter, data_end) {
+            Some(pos) => pos + delimiter.len(),
+            None => bail!("unexpected end of multipart response"),
+        };
+    }
 }
 
 impl RemoteReader {
@@ -17,34 +462,58 @@ impl RemoteReader {
             url: url.to_string(),
             handle,
             read_offset: 0,
+            retries: 0,
+            retry_delay: Duration::from_millis(500),
+            timeout: None,
+            max_ranges: 1,
+            multi_range_supported: true,
+            max_concurrent: 1,
+            max_gap: None,
+            transfer_stats: TransferStats::default(),
         }
     }
-}
 
-impl From<Error> for io::Error {
-    fn from(error: Error) -> Self {
-        io::Error::new(io::ErrorKind::Other, format!("{}", error))
+    pub fn with_options(url: &str, options: &RemoteOptions) -> Self {
+        let mut reader = Self::new(url);
+        reader.set_retries(options.retries);
+        if let Some(timeout) = options.timeout {
+            reader.set_timeout(timeout);
+        }
+        reader.set_max_ranges(options.max_ranges);
+        reader.set_max_concurrent(options.max_concurrent);
+        if let Some(max_gap) = options.max_gap {
+            reader.set_max_gap(max_gap);
+        }
+        reader
     }
-}
 
-impl io::Read for RemoteReader {
-    fn read(&mut self, buf: &mut [u8]) -> std::result::Result<usize, io::Error> {
-        let read_offset = self.read_offset;
-        self.read_at(read_offset, buf)?;
-        self.read_offset += buf.len() as u64;
-        Ok(buf.len())
+    // Use a fixed max gap size instead of choosing it from the transfer stats
+    pub fn set_max_gap(&mut self, max_gap: u64) {
+        self.max_gap = Some(max_gap);
     }
-}
 
-impl ArchiveBackend for RemoteReader {
-    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
-        if buf.is_empty() {
-            return Ok(());
-        }
+    pub fn set_max_concurrent(&mut self, max_concurrent: usize) {
+        self.max_concurrent = cmp::max(max_concurrent, 1);
+    }
 
-        let end_offset = offset + (buf.len() - 1) as u64;
-        let mut data = Vec::new();
+    pub fn set_max_ranges(&mut self, max_ranges: usize) {
+        self.max_ranges = cmp::max(max_ranges, 1);
+    }
+
+    pub fn set_retries(&mut self, retries: u32) {
+        self.retries = retries;
+    }
+
+    pub fn set_retry_delay(&mut self, retry_delay: Duration) {
+        self.retry_delay = retry_delay;
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
 
+    // Prepare the handle for a request of the given range(s)
+    fn setup_request(&mut self, range: &str) -> Result<()> {
         self.handle
             .url(&self.url)
             .chain_err(|| "unable to set url")?;
@@ -52,79 +521,511 @@ impl ArchiveBackend for RemoteReader {
             .fail_on_error(true)
             .chain_err(|| "unable to set fail on error option")?;
         self.handle
-            .range(&format!("{}-{}", offset, end_offset))
+            .range(range)
             .chain_err(|| "unable to set range")?;
+        if let Some(timeout) = self.timeout {
+            self.handle
+                .connect_timeout(timeout)
+                .chain_err(|| "unable to set connect timeout")?;
+            // Abort the transfer if it stalls for longer than the timeout
+            self.handle
+                .low_speed_limit(1)
+                .chain_err(|| "unable to set low speed limit")?;
+            self.handle
+                .low_speed_time(timeout)
+                .chain_err(|| "unable to set low speed time")?;
+        }
+        Ok(())
+    }
 
-        {
+    // Record the timings of the last transfer made using the handle
+    fn record_transfer(&mut self) {
+        if let (Ok(pretransfer), Ok(starttransfer), Ok(total), Ok(size)) = (
+            self.handle.pretransfer_time(),
+            self.handle.starttransfer_time(),
+            self.handle.total_time(),
+            self.handle.download_size(),
+        ) {
+            self.transfer_stats
+                .add_sample(pretransfer, starttransfer, total, size);
+        }
+    }
+
+    // Create a handle for a concurrent request of the given range(s)
+    fn new_concurrent_request(&self, range: &str) -> Result<Easy2<RangeResponse>> {
+        let mut handle = Easy2::new(RangeResponse::default());
+        handle.url(&self.url).chain_err(|| "unable to set url")?;
+        handle
+            .fail_on_error(true)
+            .chain_err(|| "unable to set fail on error option")?;
+        handle.range(range).chain_err(|| "unable to set range")?;
+        if let Some(timeout) = self.timeout {
+            handle
+                .connect_timeout(timeout)
+                .chain_err(|| "unable to set connect timeout")?;
+            handle
+                .low_speed_limit(1)
+                .chain_err(|| "unable to set low speed limit")?;
+            handle
+                .low_speed_time(timeout)
+                .chain_err(|| "unable to set low speed time")?;
+        }
+        Ok(handle)
+    }
+
+    // Request all given groups of chunks in a single multi-range request.
+    // Returns None if the server did not respond with the requested ranges.
+    fn request_ranges(&mut self, groups: &[(u64, Vec<u64>)]) -> Result<Option<Vec<RangePart>>> {
+        self.setup_request(&range_of_groups(groups))?;
+
+        let mut body: Vec<u8> = Vec::new();
+        let mut headers = ResponseHeaders::default();
+        let transfer_result = {
             let mut transfer = self.handle.transfer();
+            transfer
+                .header_function(|header| headers.header_line(header))
+                .chain_err(|| "transfer header failed")?;
             transfer
                 .write_function(|new_data| {
-                    data.extend_from_slice(new_data);
+                    body.extend_from_slice(new_data);
                     Ok(new_data.len())
                 })
                 .chain_err(|| "transfer write failed")?;
-
             transfer
                 .perform()
-                .chain_err(|| "failed to execute transfer")?;
+                .chain_err(|| "failed to execute transfer")
+        };
+        if !headers.ranges_ignored {
+            transfer_result?;
+            self.record_transfer();
         }
+        headers.into_parts(body, groups)
+    }
 
-        buf[..data.len()].clone_from_slice(&data[..]);
-        Ok(())
+    // Read a batch of chunk groups using a single request. Attempt is the
+    // number of attempts already made to read the batch.
+    fn read_batch(
+        &mut self,
+        batch: &[(u64, Vec<u64>)],
+        chunk_callback: &mut dyn FnMut(&[u8]) -> Result<()>,
+        mut attempt: u32,
+    ) -> Result<()> {
+        if batch.len() == 1 {
+            let (start_offset, chunk_sizes) = &batch[0];
+            return self.read_group(*start_offset, chunk_sizes, chunk_callback, attempt);
+        }
+
+        let parts = loop {
+            match self.request_ranges(batch) {
+                Ok(parts) => break parts,
+                Err(ref err) if attempt < self.retries => {
+                    self.wait_before_retry(attempt, err);
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        };
+        match parts {
+            Some(parts) => forward_parts(batch, &parts, chunk_callback),
+            None => {
+                self.multi_range_unsupported();
+                for (start_offset, chunk_sizes) in batch {
+                    self.read_in_chunks(*start_offset, chunk_sizes, chunk_callback)?;
+                }
+                Ok(())
+            }
+        }
     }
 
-    fn read_in_chunks<F: FnMut(Vec<u8>) -> Result<()>>(
+    // Read a group of chunks using a single range request. Attempt is the
+    // number of attempts already made to read the group.
+    fn read_group(
         &mut self,
         start_offset: u64,
         chunk_sizes: &[u64],
-        mut chunk_callback: F,
+        chunk_callback: &mut dyn FnMut(&[u8]) -> Result<()>,
+        mut attempt: u32,
     ) -> Result<()> {
         let tot_size: u64 = chunk_sizes.iter().sum();
+        let end_offset = start_offset + tot_size - 1;
 
-        // Create get request
-        let mut chunk_buf: Vec<u8> = vec![];
+        // Offset of the first chunk not yet received. On retry the request
+        // is resumed from this chunk.
         let mut chunk_index = 0;
-        let end_offset = start_offset + tot_size - 1;
+        let mut chunk_offset = start_offset;
 
-        self.handle
-            .url(&self.url)
-            .chain_err(|| "unable to set url")?;
+        loop {
+            let mut chunk_buf: Vec<u8> = vec![];
+            self.setup_request(&format!("{}-{}", chunk_offset, end_offset))?;
 
-        self.handle
-            .fail_on_error(true)
-            .chain_err(|| "unable to set fail on error option")?;
-        self.handle
-            .range(&format!("{}-{}", start_offset, end_offset))
-            .chain_err(|| "unable to set range")?;
+            let mut callback_result = Ok(());
+            let transfer_result = {
+                let mut transfer = self.handle.transfer();
+                transfer
+                    .write_function(|new_data| {
+                        // Got data back from server
+                        chunk_buf.extend_from_slice(new_data);
 
-        let mut transfer_result = Ok(());
-        {
-            let mut transfer = self.handle.transfer();
-            transfer
-                .write_function(|new_data| {
-                    // Got data back from server
-                    chunk_buf.extend_from_slice(new_data);
-
-                    while chunk_index < chunk_sizes.len()
-                        && chunk_buf.len() >= chunk_sizes[chunk_index] as usize
-                    {
-                        // Got a full chunk
-                        let chunk_size = chunk_sizes[chunk_index] as usize;
-                        transfer_result = chunk_callback(chunk_buf.drain(..chunk_size).collect());
-                        if transfer_result.is_err() {
-                            // TODO: Strange error to return here but the only one available?
-                            return Err(curl::easy::WriteError::Pause);
+                        while chunk_index < chunk_sizes.len()
+                            && chunk_buf.len() >= chunk_sizes[chunk_index] as usize
+                        {
+                            // Got a full chunk
+                            let chunk_size = chunk_sizes[chunk_index] as usize;
+                            callback_result = chunk_callback(&chunk_buf[..chunk_size]);
+                            if callback_result.is_err() {
+                                // TODO: Strange error to return here but the only one available?
+                                return Err(curl::easy::WriteError::Pause);
+                            }
+                            chunk_buf.drain(..chunk_size);
+                            chunk_index += 1;
+                            chunk_offset += chunk_size as u64;
                         }
-                        chunk_index += 1;
-                    }
-                    Ok(new_data.len())
-                })
-                .chain_err(|| "transfer write failed")?;
-            transfer
+                        Ok(new_data.len())
+                    })
+                    .chain_err(|| "transfer write failed")?;
+                transfer
+                    .perform()
+                    .chain_err(|| "failed to execute transfer")
+            };
+
+            // An error from the callback is not retried
+            callback_result?;
+
+            let result = transfer_result.and_then(|_| {
+                if chunk_index < chunk_sizes.len() {
+                    bail!("transfer ended before all chunks were received")
+                }
+                Ok(())
+            });
+            match result {
+                Ok(()) => {
+                    self.record_transfer();
+                    return Ok(());
+                }
+                Err(ref err) if attempt < self.retries => {
+                    self.wait_before_retry(attempt, err);
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn multi_range_unsupported(&mut self) {
+        if self.multi_range_supported {
+            warn!(
+                "Server did not respond to multi-range request, falling back to single range requests"
+            );
+            self.multi_range_supported = false;
+        }
+    }
+
+    // Read batches of chunk groups with several requests in flight at the
+    // same time. The chunks are still forwarded in order. A failed request
+    // is retried using a single request.
+    fn read_batches_concurrent(
+        &mut self,
+        groups: &[(u64, Vec<u64>)],
+        batches: &[std::ops::Range<usize>],
+        chunk_callback: &mut dyn FnMut(&[u8]) -> Result<()>,
+    ) -> Result<()> {
+        let multi = Multi::new();
+        let mut in_flight: HashMap<usize, Easy2Handle<RangeResponse>> = HashMap::new();
+        let mut completed: BTreeMap<usize, Result<Option<Vec<RangePart>>>> = BTreeMap::new();
+        let mut next_to_start = 0;
+        let mut next_to_forward = 0;
+
+        while next_to_forward < batches.len() {
+            // Keep the number of requests not yet forwarded within limit, so
+            // that the amount of buffered responses is bounded.
+            while next_to_start < batches.len()
+                && next_to_start - next_to_forward < self.max_concurrent
+            {
+                let batch = &groups[batches[next_to_start].clone()];
+                let request = self.new_concurrent_request(&range_of_groups(batch))?;
+                let mut handle = multi.add2(request).chain_err(|| "failed to add request")?;
+                handle
+                    .set_token(next_to_start)
+                    .chain_err(|| "failed to set request token")?;
+                in_flight.insert(next_to_start, handle);
+                next_to_start += 1;
+            }
+
+            multi
                 .perform()
-                .chain_err(|| "failed to execute transfer")?;
+                .chain_err(|| "failed to execute transfers")?;
+            let mut finished: Vec<(usize, std::result::Result<(), curl::Error>)> = Vec::new();
+            multi.messages(|message| {
+                if let (Ok(token), Some(result)) = (message.token(), message.result()) {
+                    finished.push((token, result));
+                }
+            });
+            for (token, result) in finished {
+                let handle = in_flight.remove(&token).expect("request in flight");
+                let mut request = multi
+                    .remove2(handle)
+                    .chain_err(|| "failed to remove request")?;
+                if result.is_ok() {
+                    if let (Ok(pretransfer), Ok(starttransfer), Ok(total), Ok(size)) = (
+                        request.pretransfer_time(),
+                        request.starttransfer_time(),
+                        request.total_time(),
+                        request.download_size(),
+                    ) {
+                        self.transfer_stats
+                            .add_sample(pretransfer, starttransfer, total, size);
+                    }
+                }
+                let response = request.get_mut();
+                let headers = mem::replace(&mut response.headers, ResponseHeaders::default());
+                let body = mem::replace(&mut response.body, Vec::new());
+                let parts = match result {
+                    Err(err) if !headers.ranges_ignored => {
+                        Err(Error::with_chain(err, "failed to execute transfer"))
+                    }
+                    _ => headers.into_parts(body, &groups[batches[token].clone()]),
+                };
+                completed.insert(token, parts);
+            }
+
+            // Forward completed responses in order
+            while let Some(parts) = completed.remove(&next_to_forward) {
+                let batch = &groups[batches[next_to_forward].clone()];
+                match parts {
+                    Ok(Some(parts)) => forward_parts(batch, &parts, chunk_callback)?,
+                    Ok(None) => {
+                        if batch.len() > 1 {
+                            self.multi_range_unsupported();
+                        }
+                        for (start_offset, chunk_sizes) in batch {
+                            self.read_in_chunks(*start_offset, chunk_sizes, chunk_callback)?;
+                        }
+                    }
+                    Err(err) => {
+                        if self.retries == 0 {
+                            return Err(err);
+                        }
+                        // The failed request counts as the first attempt
+                        self.wait_before_retry(0, &err);
+                        self.read_batch(batch, chunk_callback, 1)?;
+                    }
+                }
+                next_to_forward += 1;
+            }
+
+            if !in_flight.is_empty() {
+                multi
+                    .wait(&mut [], Duration::from_secs(1))
+                    .chain_err(|| "failed to wait for transfers")?;
+            }
+        }
+        Ok(())
+    }
+
+    // Wait before making the given retry attempt (counting from 0)
+    fn wait_before_retry(&self, attempt: u32, err: &Error) {
+        // Cap the backoff to not wait for ages between retries
+        let delay = self.retry_delay * 2u32.pow(cmp::min(attempt, 7));
+        warn!(
+            "Request to {} failed ({}), retrying in {:.1}s ({} of {})",
+            self.url,
+            err.iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<String>>()
+                .join(": "),
+            delay.as_millis() as f64 / 1000.0,
+            attempt + 1,
+            self.retries
+        );
+        thread::sleep(delay);
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        io::Error::new(io::ErrorKind::Other, format!("{}", error))
+    }
+}
+
+impl io::Read for RemoteReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::result::Result<usize, io::Error> {
+        let read_offset = self.read_offset;
+        self.read_at(read_offset, buf)?;
+        self.read_offset += buf.len() as u64;
+        Ok(buf.len())
+    }
+}
+
+impl ArchiveBackend for RemoteReader {
+    fn max_gap_size(&self) -> u64 {
+        let max_gap = self
+            .max_gap
+            .unwrap_or_else(|| self.transfer_stats.gap_size());
+        debug!(
+            "Fetching chunks separated by at most {} bytes in a single range",
+            max_gap
+        );
+        max_gap
+    }
+
+    fn read_chunk_groups(
+        &mut self,
+        groups: &[(u64, Vec<u64>)],
+        chunk_callback: &mut dyn FnMut(&[u8]) -> Result<()>,
+    ) -> Result<()> {
+        let max_ranges = if self.multi_range_supported {
+            self.max_ranges
+        } else {
+            1
+        };
+        if self.max_concurrent > 1 {
+            // Split large groups to spread them over several requests
+            let groups = split_groups(groups, MULTI_RANGE_MAX_SIZE);
+            let batches = batch_groups(&groups, max_ranges);
+            self.read_batches_concurrent(&groups, &batches, chunk_callback)
+        } else {
+            for batch in batch_groups(groups, max_ranges) {
+                self.read_batch(&groups[batch], chunk_callback, 0)?;
+            }
+            Ok(())
         }
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        let end_offset = offset + (buf.len() - 1) as u64;
+        let mut data = Vec::new();
+        let mut attempt = 0;
+
+        loop {
+            data.clear();
+            self.setup_request(&format!("{}-{}", offset, end_offset))?;
+            let result = {
+                let mut transfer = self.handle.transfer();
+                transfer
+                    .write_function(|new_data| {
+                        data.extend_from_slice(new_data);
+                        Ok(new_data.len())
+                    })
+                    .chain_err(|| "transfer write failed")?;
+
+                transfer
+                    .perform()
+                    .chain_err(|| "failed to execute transfer")
+            }
+            .and_then(|_| {
+                if data.len() < buf.len() {
+                    bail!("transfer ended before all data was received")
+                }
+                Ok(())
+            });
+            match result {
+                Ok(()) => {
+                    self.record_transfer();
+                    break;
+                }
+                Err(ref err) if attempt < self.retries => {
+                    self.wait_before_retry(attempt, err);
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        buf.clone_from_slice(&data[..buf.len()]);
+        Ok(())
+    }
+
+    fn read_in_chunks(
+        &mut self,
+        start_offset: u64,
+        chunk_sizes: &[u64],
+        chunk_callback: &mut dyn FnMut(&[u8]) -> Result<()>,
+    ) -> Result<()> {
+        self.read_group(start_offset, chunk_sizes, chunk_callback, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_byteranges() {
+        let body = b"\r\n--THIS_SEPARATES\r\n\
+Content-Type: application/octet-stream\r\n\
+Content-Range: bytes 10-15/100\r\n\
+\r\n\
+\r\n--TH\r\n\
+--THIS_SEPARATES\r\n\
+Content-Range: bytes 50-52/100\r\n\
+\r\n\
+abc\r\n\
+--THIS_SEPARATES--\r\n";
+        let boundary =
+            multipart_boundary("multipart/byteranges; boundary=THIS_SEPARATES").expect("boundary");
+        assert_eq!(boundary, "THIS_SEPARATES");
+        let parts = parse_multipart_byteranges(body, &boundary, (0, 99)).expect("parse");
+        assert_eq!(
+            parts,
+            vec![
+                RangePart {
+                    offset: 10,
+                    data: b"\r\n--TH".to_vec(),
+                },
+                RangePart {
+                    offset: 50,
+                    data: b"abc".to_vec(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_truncated_byteranges() {
+        let body = b"--B\r\nContent-Range: bytes 0-9/100\r\n\r\nabc";
+        assert!(parse_multipart_byteranges(body, "B", (0, 99)).is_err());
+    }
+
+    #[test]
+    fn parse_invalid_byteranges() {
+        // End before start
+        let body = b"--B\r\nContent-Range: bytes 9-0/100\r\n\r\nabc\r\n--B--\r\n";
+        assert!(parse_multipart_byteranges(body, "B", (0, 99)).is_err());
+        // Outside of requested range
+        let body = b"--B\r\nContent-Range: bytes 0-2/100\r\n\r\nabc\r\n--B--\r\n";
+        assert!(parse_multipart_byteranges(body, "B", (0, 99)).is_ok());
+        assert!(parse_multipart_byteranges(body, "B", (1, 99)).is_err());
+        assert!(parse_multipart_byteranges(body, "B", (0, 1)).is_err());
+        assert!(parse_content_range("bytes 5-4/10").is_err());
+    }
+
+    #[test]
+    fn split_and_batch() {
+        let groups = vec![(0, vec![4, 4, 4]), (20, vec![10]), (40, vec![2, 2])];
+        let split = split_groups(&groups, 8);
+        assert_eq!(
+            split,
+            vec![
+                (0, vec![4, 4]),
+                (8, vec![4]),
+                (20, vec![10]),
+                (40, vec![2, 2])
+            ]
+        );
+        assert_eq!(batch_groups(&split, 3), vec![0..3, 3..4]);
+        assert_eq!(batch_groups(&split, 1), vec![0..1, 1..2, 2..3, 3..4]);
+    }
 
-        transfer_result
+    #[test]
+    fn not_byteranges() {
+        assert_eq!(multipart_boundary("application/octet-stream"), None);
+        assert_eq!(
+            multipart_boundary("multipart/byteranges; boundary=\"abc\""),
+            Some("abc".to_string())
+        );
     }
 }
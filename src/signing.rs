@@ -0,0 +1,107 @@
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer};
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+use crate::errors::*;
+
+// Signing of archive headers using Ed25519. The signature signs the header
+// hash, which in turn covers the full header.
+pub const SIGNATURE_SIZE: usize = 64;
+
+const KEY_SIZE: usize = 32;
+
+// DER prefix of an Ed25519 private key in PKCS#8 format
+const PRIVATE_KEY_DER_PREFIX: &[u8] = &[
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+
+// DER prefix of an Ed25519 public key in SubjectPublicKeyInfo format
+const PUBLIC_KEY_DER_PREFIX: &[u8] = &[
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+// Get the raw key from key data, which is either the raw key itself or the
+// key in DER format (as written by 'openssl pkey -outform DER').
+fn raw_key<'a>(key_data: &'a [u8], der_prefix: &[u8]) -> Option<&'a [u8]> {
+    if key_data.len() == KEY_SIZE {
+        Some(key_data)
+    } else if key_data.len() == der_prefix.len() + KEY_SIZE && key_data.starts_with(der_prefix) {
+        Some(&key_data[der_prefix.len()..])
+    } else {
+        None
+    }
+}
+
+pub fn signing_key_from_bytes(key_data: &[u8]) -> Result<Keypair> {
+    let key =
+        raw_key(key_data, PRIVATE_KEY_DER_PREFIX).chain_err(|| "invalid Ed25519 private key")?;
+    let secret = SecretKey::from_bytes(key).chain_err(|| "invalid Ed25519 private key")?;
+    let public = PublicKey::from(&secret);
+    Ok(Keypair { secret, public })
+}
+
+pub fn verify_key_from_bytes(key_data: &[u8]) -> Result<PublicKey> {
+    let key =
+        raw_key(key_data, PUBLIC_KEY_DER_PREFIX).chain_err(|| "invalid Ed25519 public key")?;
+    PublicKey::from_bytes(key).chain_err(|| "invalid Ed25519 public key")
+}
+
+// Load a private key for signing archives
+pub fn load_signing_key(path: &Path) -> Result<Keypair> {
+    let key_data =
+        fs::read(path).chain_err(|| format!("unable to read key ({})", path.display()))?;
+    signing_key_from_bytes(&key_data).chain_err(|| format!("invalid key ({})", path.display()))
+}
+
+// Load a public key for verifying signed archives
+pub fn load_verify_key(path: &Path) -> Result<PublicKey> {
+    let key_data =
+        fs::read(path).chain_err(|| format!("unable to read key ({})", path.display()))?;
+    verify_key_from_bytes(&key_data).chain_err(|| format!("invalid key ({})", path.display()))
+}
+
+pub fn sign_header(key: &Keypair, header_checksum: &[u8]) -> Vec<u8> {
+    key.sign(header_checksum).to_bytes().to_vec()
+}
+
+pub fn verify_header(key: &PublicKey, header_checksum: &[u8], signature: &[u8]) -> Result<()> {
+    let signature = Signature::try_from(signature).chain_err(|| "invalid header signature")?;
+    key.verify_strict(header_checksum, &signature)
+        .chain_err(|| "archive header signature verification failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify() {
+        let key = signing_key_from_bytes(&[7; 32]).unwrap();
+        let verify_key = verify_key_from_bytes(key.public.as_bytes()).unwrap();
+        let checksum = [1; 64];
+        let signature = sign_header(&key, &checksum);
+        assert_eq!(signature.len(), SIGNATURE_SIZE);
+        assert!(verify_header(&verify_key, &checksum, &signature).is_ok());
+        assert!(verify_header(&verify_key, &[2; 64], &signature).is_err());
+
+        let other_key = signing_key_from_bytes(&[8; 32]).unwrap();
+        assert!(verify_header(&other_key.public, &checksum, &signature).is_err());
+    }
+
+    #[test]
+    fn der_keys() {
+        let mut private_der = PRIVATE_KEY_DER_PREFIX.to_vec();
+        private_der.extend(&[7; 32]);
+        let key = signing_key_from_bytes(&private_der).unwrap();
+        assert_eq!(key.secret.as_bytes(), &[7; 32]);
+
+        let mut public_der = PUBLIC_KEY_DER_PREFIX.to_vec();
+        public_der.extend(key.public.as_bytes());
+        let public = verify_key_from_bytes(&public_der).unwrap();
+        assert_eq!(public, key.public);
+
+        assert!(signing_key_from_bytes(&public_der).is_err());
+        assert!(verify_key_from_bytes(&[0; 40]).is_err());
+    }
+}
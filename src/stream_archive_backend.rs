@@ -0,0 +1,103 @@
+use std::io;
+use std::io::prelude::*;
+
+use crate::archive_reader::ArchiveBackend;
+use crate::errors::*;
+
+// Archive backend reading from a non-seekable stream, like stdin.
+// Data can only be read in archive order, data before the requested offset
+// is skipped.
+pub struct StreamReader<R> {
+    input: R,
+    position: u64,
+}
+
+impl<R: Read> StreamReader<R> {
+    pub fn new(input: R) -> Self {
+        StreamReader { input, position: 0 }
+    }
+
+    // Skip stream data until the given offset
+    fn skip_to(&mut self, offset: u64) -> Result<()> {
+        if offset < self.position {
+            bail!(
+                "unable to read backwards in archive stream (at {}, requested {})",
+                self.position,
+                offset
+            );
+        }
+        let skip = offset - self.position;
+        let skipped = io::copy(&mut (&mut self.input).take(skip), &mut io::sink())
+            .chain_err(|| "failed to read archive stream")?;
+        self.position += skipped;
+        if skipped < skip {
+            bail!("unexpected end of archive stream");
+        }
+        Ok(())
+    }
+
+    fn read_exact_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        self.skip_to(offset)?;
+        self.input
+            .read_exact(buf)
+            .chain_err(|| "failed to read archive stream")?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+}
+
+impl<R: Read> io::Read for StreamReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::result::Result<usize, io::Error> {
+        let size = self.input.read(buf)?;
+        self.position += size as u64;
+        Ok(size)
+    }
+}
+
+impl<R: Read> ArchiveBackend for StreamReader<R> {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        self.read_exact_at(offset, buf)
+    }
+
+    fn read_in_chunks(
+        &mut self,
+        start_offset: u64,
+        chunk_sizes: &[u64],
+        chunk_callback: &mut dyn FnMut(&[u8]) -> Result<()>,
+    ) -> Result<()> {
+        let mut offset = start_offset;
+        let mut buf: Vec<u8> = Vec::new();
+        for chunk_size in chunk_sizes {
+            buf.resize(*chunk_size as usize, 0);
+            self.read_exact_at(offset, &mut buf[..])?;
+            chunk_callback(&buf)?;
+            offset += chunk_size;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_forward_only() {
+        let mut stream = StreamReader::new(&b"0123456789abcdef"[..]);
+        let mut buf = [0; 2];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"01");
+
+        let mut chunks = Vec::new();
+        stream
+            .read_chunk_groups(&[(4, vec![2, 1]), (10, vec![3])], &mut |chunk| {
+                chunks.push(chunk.to_vec());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(chunks, vec![b"45".to_vec(), b"6".to_vec(), b"abc".to_vec()]);
+
+        assert!(stream.read_at(0, &mut buf).is_err());
+        assert!(stream.read_at(15, &mut buf).is_err());
+    }
+}